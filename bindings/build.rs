@@ -5,7 +5,7 @@ fn main() {
             EvtRender, EvtSubscribe, EvtUpdateBookmark,
         },
         Windows::Win32::System::SystemServices::{ HANDLE, PWSTR},
-        Windows::Win32::System::Threading::CreateEventW,
+        Windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject},
         Windows::Win32::System::WindowsProgramming::CloseHandle,
     );
 }
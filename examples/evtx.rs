@@ -0,0 +1,22 @@
+use std::env;
+use win_events::{event::Output, event::WinLogEvent, evtx};
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(p) => p,
+        None => {
+            println!("usage: evtx <file.evtx>");
+            return;
+        }
+    };
+
+    let r = evtx::Reader::open(path, Output::Parsed).unwrap();
+
+    for event in r {
+        match event {
+            Ok(WinLogEvent::Parsed(e)) => println!("{} - {}", e.event_id, e.record_id),
+            Ok(event) => println!("{:?}", event),
+            Err(err) => println!("unable to parse event {}", err),
+        }
+    }
+}
@@ -1,6 +1,9 @@
+#[cfg(windows)]
 use std::{thread, time};
+#[cfg(windows)]
 use win_events::{error::ErrorKind, event::WinLogEvent, filter, filter::Level, reader};
 
+#[cfg(windows)]
 fn main() {
     let f1 = filter::Config {
         channel: "Application".into(),
@@ -9,16 +12,26 @@ fn main() {
         event_id: Some("1,16384,-3433,100-200,-300-400".to_string()),
         level: Some(vec![Level::Information, Level::Warning]),
         ignore_older: Some(43200),
+        since: None,
+        until: None,
         provider: None,
+        event_data: None,
+        keywords: None,
+        exclude: None,
     };
 
-    let f2 = filter::Config {
-        channel: "Security".into(),
-        level: Some(vec![Level::Error, Level::Information]),
-        ..Default::default()
-    };
+    let f2 = filter::QueryBuilder::channel("Security")
+        .level(Level::Error)
+        .level(Level::Information)
+        .build();
 
-    let query = filter::build_query(vec![f1, f2]);
+    let query = match filter::build_query(vec![f1, f2]) {
+        Ok(query) => query,
+        Err(err) => {
+            println!("invalid filter {}", err);
+            return;
+        }
+    };
 
     let config = reader::Config {
         read_oldest: true,
@@ -28,7 +41,8 @@ fn main() {
             <Bookmark Channel='Application' RecordId='6672'/>
             <Bookmark Channel='Security' RecordId='30044' IsCurrent='true'/>
           </BookmarkList>"#
-                .to_owned(),
+                .parse()
+                .unwrap(),
         ),
         output: reader::Output::Parsed,
     };
@@ -53,7 +67,7 @@ fn main() {
                     println!("unable to get event {}", err);
                     continue;
                 }
-                ErrorKind::Subscription => {
+                _ => {
                     println!("error occurred in event subscription {}", err);
                     break;
                 }
@@ -65,3 +79,8 @@ fn main() {
         println!("xml bookmark: {}", xml)
     };
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("windows event reader is only available on windows");
+}
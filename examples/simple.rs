@@ -1,40 +1,33 @@
-use std::{thread, time};
-use win_events::{error::ErrorKind, event::WinLogEvent, reader};
-
+#[cfg(windows)]
+use std::time;
+#[cfg(windows)]
+use win_events::{
+    event::WinLogEvent,
+    reader,
+    source::{EventSource, Mode},
+};
+
+#[cfg(windows)]
 fn main() {
     let mut config = reader::Config::default();
 
     config.read_oldest = true;
     config.output = reader::Output::Json;
 
-    let r = reader::Reader::init(config).unwrap();
-
-    loop {
-        match r.next() {
-            Ok(event) => match event {
-                WinLogEvent::Xml(xml) => println!("{:?}", xml),
-
-                WinLogEvent::Raw(e) => println!("{:?}", e),
-
-                WinLogEvent::Parsed(e) => println!("{:?}", e),
-
-                WinLogEvent::Json(json) => println!("{}", json),
-            },
-
-            Err(err) => match err.kind {
-                ErrorKind::NoMoreLogs => {
-                    thread::sleep(time::Duration::from_secs(2));
-                    continue;
-                }
-                ErrorKind::Event => {
-                    println!("unable to get event {}", err);
-                    continue;
-                }
-                ErrorKind::Subscription => {
-                    println!("error occurred in event subscription {}", err);
-                    break;
-                }
-            },
+    let mut r = reader::Reader::init(config).unwrap();
+
+    // wait and check again for new events every 2 seconds
+    for event in (&mut r).events(Mode::Follow(time::Duration::from_secs(2))) {
+        match event {
+            Ok(WinLogEvent::Xml(xml)) => println!("{:?}", xml),
+
+            Ok(WinLogEvent::Raw(e)) => println!("{:?}", e),
+
+            Ok(WinLogEvent::Parsed(e)) => println!("{:?}", e),
+
+            Ok(WinLogEvent::Json(json)) => println!("{}", json),
+
+            Err(err) => println!("unable to get event {}", err),
         }
     }
 
@@ -42,3 +35,8 @@ fn main() {
         println!("xml bookmark: {}", xml)
     };
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("windows event reader is only available on windows");
+}
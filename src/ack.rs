@@ -0,0 +1,369 @@
+use crate::bookmark::Bookmark;
+use crate::checkpoint::{BookmarkStore, Policy, Schedule};
+use crate::error::{Error, ErrorKind, Result};
+use crate::event::WinLogEvent;
+use crate::source::{event_position, EventSource};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+// Position identifies a delivered event, it is passed back to ack once the
+// event is handled by the consumer.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    sequence: u64,
+    record: Option<(String, u64)>,
+}
+
+impl Position {
+    // delivery order of event
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn channel(&self) -> Option<&str> {
+        self.record.as_ref().map(|(c, _)| c.as_str())
+    }
+
+    pub fn record_id(&self) -> Option<u64> {
+        self.record.as_ref().map(|(_, id)| *id)
+    }
+}
+
+#[derive(Debug)]
+pub struct Delivery {
+    pub position: Position,
+    pub event: WinLogEvent,
+}
+
+// AckSource gives at-least-once delivery. Saved bookmark only moves past an
+// event once it and every event delivered before it are acknowledged, events
+// not acknowledged before a crash are delivered again on next run. Failed
+// saves are kept in save_error and retried, they never fail receive or ack.
+pub struct AckSource<S: EventSource, B: BookmarkStore> {
+    source: S,
+    store: B,
+    schedule: Schedule,
+    save_error: Option<Error>,
+    committed: Bookmark,
+    next_sequence: u64,
+    outstanding: BTreeMap<u64, Option<(String, u64)>>,
+    acked: BTreeSet<u64>,
+    closed: bool,
+}
+
+impl<S: EventSource, B: BookmarkStore> AckSource<S, B> {
+    // source should be started from bookmark loaded from the same store
+    pub fn new(source: S, store: B, policy: Policy) -> Result<Self> {
+        let committed = store.load()?.unwrap_or_default();
+
+        Ok(Self {
+            source,
+            store,
+            schedule: Schedule::new(policy),
+            save_error: None,
+            committed,
+            next_sequence: 0,
+            outstanding: BTreeMap::new(),
+            acked: BTreeSet::new(),
+            closed: false,
+        })
+    }
+
+    // next event with its position, NoMoreLogs is returned when there are no
+    // events to pull
+    pub fn receive(&mut self) -> Result<Delivery> {
+        let event = match self.source.next_event() {
+            Ok(event) => event,
+            Err(err) => {
+                if err.kind == ErrorKind::NoMoreLogs {
+                    self.record(0);
+                }
+                return Err(err);
+            }
+        };
+
+        let position = Position {
+            sequence: self.next_sequence,
+            record: event_position(&event),
+        };
+        self.next_sequence += 1;
+        self.outstanding
+            .insert(position.sequence, position.record.clone());
+
+        Ok(Delivery { position, event })
+    }
+
+    // marks event as handled, unknown or already acknowledged positions are ignored
+    pub fn ack(&mut self, position: &Position) -> Result<()> {
+        if !self.outstanding.contains_key(&position.sequence) {
+            return Ok(());
+        }
+        self.acked.insert(position.sequence);
+
+        let mut advanced = 0;
+        while let Some(first) = self.outstanding.keys().next().copied() {
+            if !self.acked.remove(&first) {
+                break;
+            }
+            if let Some(Some((channel, record_id))) = self.outstanding.remove(&first) {
+                self.committed.set(&channel, record_id);
+            }
+            advanced += 1;
+        }
+
+        self.record(advanced);
+        Ok(())
+    }
+
+    // bookmark of acknowledged events
+    pub fn committed(&self) -> &Bookmark {
+        &self.committed
+    }
+
+    // number of delivered events waiting for ack
+    pub fn unacked(&self) -> usize {
+        self.outstanding.len() - self.acked.len()
+    }
+
+    // saves committed bookmark to store
+    pub fn commit(&mut self) -> Result<()> {
+        self.store.save(&self.committed)?;
+        self.schedule.saved();
+        self.save_error = None;
+        Ok(())
+    }
+
+    // error of the last policy save, None once a save succeeds
+    pub fn save_error(&self) -> Option<&Error> {
+        self.save_error.as_ref()
+    }
+
+    pub fn wait(&mut self, timeout: Duration) {
+        self.source.wait(timeout)
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        let saved = if self.schedule.due_on_close() {
+            self.commit()
+        } else {
+            Ok(())
+        };
+
+        self.source.close()?;
+        saved
+    }
+
+    // failed save stays due and is retried with the next call
+    fn record(&mut self, count: u64) {
+        if self.schedule.record(count) {
+            if let Err(err) = self.commit() {
+                self.save_error = Some(err);
+            }
+        }
+    }
+}
+
+impl<S: EventSource, B: BookmarkStore> Drop for AckSource<S, B> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use crate::source::MemorySource;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // store shared between runs, fails while broken is set
+    #[derive(Default, Clone)]
+    struct SharedStore {
+        bookmark: Rc<RefCell<Option<Bookmark>>>,
+        saves: Rc<RefCell<usize>>,
+        broken: Rc<RefCell<bool>>,
+    }
+
+    impl BookmarkStore for SharedStore {
+        fn load(&self) -> Result<Option<Bookmark>> {
+            Ok(self.bookmark.borrow().clone())
+        }
+
+        fn save(&mut self, bookmark: &Bookmark) -> Result<()> {
+            if *self.broken.borrow() {
+                return Err(Error {
+                    kind: ErrorKind::File,
+                    message: "disk full".to_owned(),
+                });
+            }
+            *self.bookmark.borrow_mut() = Some(bookmark.clone());
+            *self.saves.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    impl SharedStore {
+        fn saved(&self) -> Option<u64> {
+            self.bookmark
+                .borrow()
+                .as_ref()
+                .and_then(|b| b.record_id("Security"))
+        }
+    }
+
+    // Security events 1 to count after record id stored in store, like a
+    // reader started from the stored bookmark
+    fn source(store: &SharedStore, count: u64) -> MemorySource {
+        let start = store.saved().unwrap_or(0);
+        MemorySource::new(
+            (start + 1..=count)
+                .map(|record_id| {
+                    WinLogEvent::Parsed(Event {
+                        channel: "Security".to_owned(),
+                        record_id,
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn ack_source(
+        store: &SharedStore,
+        count: u64,
+        policy: Policy,
+    ) -> AckSource<MemorySource, SharedStore> {
+        AckSource::new(source(store, count), store.clone(), policy).unwrap()
+    }
+
+    fn every(n: u64) -> Policy {
+        Policy {
+            every_events: Some(n),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn out_of_order_ack_waits_for_earlier_events() {
+        let store = SharedStore::default();
+        let mut source = ack_source(&store, 3, Policy::default());
+
+        let first = source.receive().unwrap().position;
+        let second = source.receive().unwrap().position;
+        let third = source.receive().unwrap().position;
+        assert_eq!(
+            (first.sequence(), second.record_id(), third.channel()),
+            (0, Some(2), Some("Security"))
+        );
+
+        source.ack(&third).unwrap();
+        source.ack(&second).unwrap();
+        assert!(source.committed().is_empty());
+        assert_eq!(source.unacked(), 1);
+
+        source.ack(&first).unwrap();
+        assert_eq!(source.committed().record_id("Security"), Some(3));
+        assert_eq!(source.unacked(), 0);
+    }
+
+    #[test]
+    fn commits_as_policy_says() {
+        let store = SharedStore::default();
+        let mut source = ack_source(&store, 5, every(2));
+
+        let positions: Vec<Position> = (0..5).map(|_| source.receive().unwrap().position).collect();
+
+        source.ack(&positions[0]).unwrap();
+        assert_eq!(*store.saves.borrow(), 0);
+        source.ack(&positions[1]).unwrap();
+        assert_eq!((*store.saves.borrow(), store.saved()), (1, Some(2)));
+
+        // only acked events count
+        source.ack(&positions[3]).unwrap();
+        assert_eq!(*store.saves.borrow(), 1);
+        source.ack(&positions[2]).unwrap();
+        assert_eq!((*store.saves.borrow(), store.saved()), (2, Some(4)));
+
+        source.ack(&positions[4]).unwrap();
+        source.close().unwrap();
+        assert_eq!((*store.saves.borrow(), store.saved()), (3, Some(5)));
+    }
+
+    #[test]
+    fn redelivers_after_restart() {
+        let store = SharedStore::default();
+        {
+            let mut source = ack_source(&store, 4, Policy::default());
+            let first = source.receive().unwrap().position;
+            let second = source.receive().unwrap().position;
+            source.receive().unwrap();
+            source.ack(&first).unwrap();
+            source.ack(&second).unwrap();
+            // dropped with event 3 unacked, like a crash after delivery
+        }
+        assert_eq!(store.saved(), Some(2));
+
+        let mut source = ack_source(&store, 4, Policy::default());
+        assert_eq!(source.committed().record_id("Security"), Some(2));
+        let ids: Vec<Option<u64>> = (0..2)
+            .map(|_| source.receive().unwrap().position.record_id())
+            .collect();
+        assert_eq!(ids, vec![Some(3), Some(4)]);
+        assert_eq!(source.receive().unwrap_err().kind, ErrorKind::NoMoreLogs);
+    }
+
+    #[test]
+    fn unknown_and_duplicate_positions_are_ignored() {
+        let store = SharedStore::default();
+        let mut source = ack_source(&store, 2, Policy::default());
+
+        let first = source.receive().unwrap().position;
+        let second = source.receive().unwrap().position;
+
+        let unknown = Position {
+            sequence: 99,
+            record: Some(("Security".to_owned(), 99)),
+        };
+        source.ack(&unknown).unwrap();
+        source.ack(&second).unwrap();
+        source.ack(&second).unwrap();
+        assert!(source.committed().is_empty());
+        assert_eq!(source.unacked(), 1);
+
+        source.ack(&first).unwrap();
+        source.ack(&first).unwrap();
+        assert_eq!(source.committed().record_id("Security"), Some(2));
+        assert_eq!(source.unacked(), 0);
+    }
+
+    #[test]
+    fn failed_save_keeps_source_result() {
+        let store = SharedStore::default();
+        *store.broken.borrow_mut() = true;
+        let policy = Policy {
+            every: Some(Duration::from_secs(0)),
+            ..Default::default()
+        };
+        let mut source = ack_source(&store, 1, policy);
+
+        let first = source.receive().unwrap().position;
+        source.ack(&first).unwrap();
+        assert_eq!(source.save_error().unwrap().message, "disk full");
+
+        // NoMoreLogs is returned while the due save fails again
+        assert_eq!(source.receive().unwrap_err().kind, ErrorKind::NoMoreLogs);
+        assert!(source.save_error().is_some());
+
+        *store.broken.borrow_mut() = false;
+        assert_eq!(source.receive().unwrap_err().kind, ErrorKind::NoMoreLogs);
+        assert!(source.save_error().is_none());
+        assert_eq!(store.saved(), Some(1));
+    }
+}
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+
+// classic drivers logging IO_ERROR_LOG_PACKET as event binary data
+const IO_ERROR_PROVIDERS: &[&str] = &[
+    "disk", "Ntfs", "atapi", "storahci", "stornvme", "iaStorV", "volmgr", "volsnap", "partmgr",
+    "cdrom",
+];
+
+const SERVICE_CONTROL_MANAGER: &str = "Service Control Manager";
+
+// fixed part of IO_ERROR_LOG_PACKET, dump data follows
+const IO_ERROR_HEADER_SIZE: usize = 40;
+
+// BinaryData is EventData Binary decoded by the layout its provider uses
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum BinaryData {
+    // utf-16 text, Service Control Manager writes service name and state
+    // like TrustedInstaller/4
+    Text(String),
+    IoError(IoError),
+}
+
+// IoError is IO_ERROR_LOG_PACKET of disk, ntfs and storage driver events
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IoError {
+    pub major_function_code: u8,
+    pub retry_count: u8,
+    pub dump_data_size: u16,
+    pub number_of_strings: u16,
+    pub string_offset: u16,
+    pub event_category: u16,
+    // ntstatus, same as event id with its qualifiers
+    pub error_code: u32,
+    pub unique_error_value: u32,
+    pub final_status: u32,
+    pub sequence_number: u32,
+    pub io_control_code: u32,
+    pub device_offset: i64,
+    pub dump_data: Vec<u32>,
+}
+
+impl BinaryData {
+    // layout of provider binary data, None for other providers or data
+    // which doesn't fit the layout
+    pub fn decode(provider: &str, data: &[u8]) -> Option<Self> {
+        if provider.eq_ignore_ascii_case(SERVICE_CONTROL_MANAGER) {
+            return decode_text(data).map(BinaryData::Text);
+        }
+
+        if IO_ERROR_PROVIDERS
+            .iter()
+            .any(|p| p.eq_ignore_ascii_case(provider))
+        {
+            return IoError::decode(data).map(BinaryData::IoError);
+        }
+
+        None
+    }
+}
+
+impl IoError {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < IO_ERROR_HEADER_SIZE {
+            return None;
+        }
+
+        let dump_data_size = read_u16(data, 2);
+        let dump =
+            data.get(IO_ERROR_HEADER_SIZE..IO_ERROR_HEADER_SIZE + dump_data_size as usize)?;
+
+        Some(IoError {
+            major_function_code: data[0],
+            retry_count: data[1],
+            dump_data_size,
+            number_of_strings: read_u16(data, 4),
+            string_offset: read_u16(data, 6),
+            event_category: read_u16(data, 8),
+            error_code: read_u32(data, 12),
+            unique_error_value: read_u32(data, 16),
+            final_status: read_u32(data, 20),
+            sequence_number: read_u32(data, 24),
+            io_control_code: read_u32(data, 28),
+            device_offset: read_u64(data, 32) as i64,
+            dump_data: dump.chunks_exact(4).map(|c| read_u32(c, 0)).collect(),
+        })
+    }
+}
+
+// Binary element text, two hex digits per byte
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits = hex.trim().as_bytes().chunks_exact(2);
+
+    if !digits.remainder().is_empty() {
+        return None;
+    }
+
+    digits
+        .map(|b| {
+            std::str::from_utf8(b)
+                .ok()
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect()
+}
+
+// utf-16 little endian text without trailing nulls
+fn decode_text(data: &[u8]) -> Option<String> {
+    let chars = data.chunks_exact(2);
+
+    if !chars.remainder().is_empty() {
+        return None;
+    }
+
+    let text: Vec<u16> = chars.map(|c| read_u16(c, 0)).collect();
+    String::from_utf16(&text)
+        .ok()
+        .map(|t| t.trim_end_matches('\0').to_owned())
+}
+
+// little endian integers at offset, callers check length
+pub(crate) fn read_u16(b: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([b[offset], b[offset + 1]])
+}
+
+pub(crate) fn read_u32(b: &[u8], offset: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&b[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+pub(crate) fn read_u64(b: &[u8], offset: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&b[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    // IO_ERROR_LOG_PACKET with two dump data words
+    fn io_error_packet() -> Vec<u8> {
+        let mut b = vec![0x03, 0x01];
+        b.extend(&8u16.to_le_bytes());
+        b.extend(&0u16.to_le_bytes());
+        b.extend(&0x78u16.to_le_bytes());
+        b.extend(&0u16.to_le_bytes());
+        b.extend(&0u16.to_le_bytes());
+        b.extend(&0xc004_0007u32.to_le_bytes());
+        b.extend(&0u32.to_le_bytes());
+        b.extend(&0xc000_0185u32.to_le_bytes());
+        b.extend(&5u32.to_le_bytes());
+        b.extend(&0u32.to_le_bytes());
+        b.extend(&(-4096i64).to_le_bytes());
+        b.extend(&0x11u32.to_le_bytes());
+        b.extend(&0x22u32.to_le_bytes());
+        b
+    }
+
+    #[test]
+    fn reads_little_endian() {
+        let b = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+        assert_eq!(read_u16(&b, 1), 0x0302);
+        assert_eq!(read_u32(&b, 1), 0x0504_0302);
+        assert_eq!(read_u64(&b, 1), 0x0908_0706_0504_0302);
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff1A"), Some(vec![0x00, 0xff, 0x1a]));
+        assert_eq!(decode_hex(" 0102\n"), Some(vec![1, 2]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("012"), None);
+        assert_eq!(decode_hex("0g"), None);
+    }
+
+    #[test]
+    fn decodes_service_text() {
+        let mut data = utf16("TrustedInstaller/4");
+        data.extend(&[0, 0]);
+
+        let decoded = BinaryData::decode("service control manager", &data);
+        assert_eq!(decoded, Some(BinaryData::Text("TrustedInstaller/4".into())));
+
+        assert_eq!(BinaryData::decode(SERVICE_CONTROL_MANAGER, &[0x41]), None);
+    }
+
+    #[test]
+    fn decodes_io_error() {
+        let decoded = match BinaryData::decode("disk", &io_error_packet()) {
+            Some(BinaryData::IoError(e)) => e,
+            d => panic!("unexpected data {:?}", d),
+        };
+
+        assert_eq!(
+            decoded,
+            IoError {
+                major_function_code: 3,
+                retry_count: 1,
+                dump_data_size: 8,
+                string_offset: 0x78,
+                error_code: 0xc004_0007,
+                final_status: 0xc000_0185,
+                sequence_number: 5,
+                device_offset: -4096,
+                dump_data: vec![0x11, 0x22],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_short_io_error() {
+        let packet = io_error_packet();
+        assert_eq!(IoError::decode(&packet[..IO_ERROR_HEADER_SIZE - 1]), None);
+        // dump data size past end of data
+        assert_eq!(IoError::decode(&packet[..packet.len() - 1]), None);
+
+        assert_eq!(BinaryData::decode("Application Error", &packet), None);
+    }
+}
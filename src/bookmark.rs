@@ -0,0 +1,398 @@
+use crate::error::{Error, ErrorKind, Result};
+use crate::event::{Event, RawEvent};
+
+use quick_xml::{escape::escape, events::Event as QuickXmlEvent, Reader as QuickXmlReader};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Bookmark is the last read record id of each channel, same information as
+// windows bookmark xml. Channel names are case-insensitive like in windows,
+// a channel keeps the spelling it was first set with:
+// <BookmarkList>
+//   <Bookmark Channel='Application' RecordId='6672'/>
+//   <Bookmark Channel='Security' RecordId='30044' IsCurrent='true'/>
+// </BookmarkList>
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Bookmark {
+    channels: BTreeMap<String, u64>,
+    current: Option<String>,
+}
+
+impl Bookmark {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = QuickXmlReader::from_str(xml);
+        reader.trim_text(true);
+
+        let mut bookmark = Bookmark::default();
+        let mut buf = Vec::new();
+        let mut has_list = false;
+
+        loop {
+            match reader.read_event(&mut buf) {
+                Ok(QuickXmlEvent::Start(ref e)) | Ok(QuickXmlEvent::Empty(ref e)) => {
+                    match e.name() {
+                        b"BookmarkList" => has_list = true,
+                        b"Bookmark" => {
+                            let mut channel = None;
+                            let mut record_id = None;
+                            let mut current = false;
+
+                            for a in e.attributes() {
+                                let att = a.map_err(|e| bookmark_error(&e.to_string()))?;
+                                let value = att
+                                    .unescape_and_decode_value(&reader)
+                                    .map_err(|e| bookmark_error(&e.to_string()))?;
+
+                                match att.key {
+                                    b"Channel" => channel = Some(value),
+                                    b"RecordId" => match value.parse::<u64>() {
+                                        Ok(id) => record_id = Some(id),
+                                        Err(_) => {
+                                            return Err(bookmark_error(&format!(
+                                                "invalid record id '{}'",
+                                                value
+                                            )))
+                                        }
+                                    },
+                                    b"IsCurrent" => current = value == "true",
+                                    _ => (),
+                                }
+                            }
+
+                            match (channel, record_id) {
+                                (Some(channel), Some(id)) => {
+                                    let channel = bookmark.insert(&channel, id);
+                                    if current {
+                                        bookmark.current = Some(channel);
+                                    }
+                                }
+                                _ => {
+                                    return Err(bookmark_error(
+                                        "bookmark without channel or record id",
+                                    ))
+                                }
+                            }
+                        }
+                        n => {
+                            return Err(bookmark_error(&format!(
+                                "unexpected element '{}'",
+                                String::from_utf8_lossy(n)
+                            )))
+                        }
+                    }
+                }
+                Ok(QuickXmlEvent::Eof) => break,
+                Err(e) => return Err(bookmark_error(&e.to_string())),
+                _ => (),
+            }
+            buf.clear();
+        }
+
+        if !has_list {
+            return Err(bookmark_error("missing BookmarkList element"));
+        }
+
+        Ok(bookmark)
+    }
+
+    pub fn to_xml(&self) -> String {
+        let bookmarks: Vec<String> = self
+            .channels
+            .iter()
+            .map(|(channel, id)| {
+                let current = if self.current.as_ref() == Some(channel) {
+                    " IsCurrent='true'"
+                } else {
+                    ""
+                };
+                format!(
+                    "<Bookmark Channel='{}' RecordId='{}'{}/>",
+                    String::from_utf8_lossy(&escape(channel.as_bytes())),
+                    id,
+                    current
+                )
+            })
+            .collect();
+
+        format!("<BookmarkList>{}</BookmarkList>", bookmarks.join(""))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    // last read record id of channel
+    pub fn record_id(&self, channel: &str) -> Option<u64> {
+        self.key(channel)
+            .and_then(|c| self.channels.get(c))
+            .copied()
+    }
+
+    // channel of the last read event
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.channels.iter().map(|(c, id)| (c.as_str(), *id))
+    }
+
+    // sets position of channel and marks it as current
+    pub fn set(&mut self, channel: &str, record_id: u64) {
+        self.current = Some(self.insert(channel, record_id));
+    }
+
+    pub fn remove(&mut self, channel: &str) -> Option<u64> {
+        let channel = self.key(channel)?.to_owned();
+        if self.current.as_ref() == Some(&channel) {
+            self.current = None;
+        }
+        self.channels.remove(&channel)
+    }
+
+    pub fn advance(&mut self, event: &Event) {
+        self.set(&event.channel, event.record_id);
+    }
+
+    pub fn advance_raw(&mut self, event: &RawEvent) {
+        if let Some(id) = event.system.event_record_id {
+            self.set(&event.system.channel, id);
+        }
+    }
+
+    // keeps the furthest record id of each channel from both bookmarks
+    pub fn merge(&mut self, other: &Bookmark) {
+        for (channel, id) in &other.channels {
+            match self.record_id(channel) {
+                Some(own) if own >= *id => (),
+                _ => {
+                    self.insert(channel, *id);
+                }
+            }
+        }
+
+        if self.current.is_none() {
+            self.current = other
+                .current
+                .as_deref()
+                .and_then(|c| self.key(c))
+                .map(|c| c.to_owned());
+        }
+    }
+
+    // true when other bookmark is further than self in any channel
+    pub fn is_behind(&self, other: &Bookmark) -> bool {
+        other
+            .channels
+            .iter()
+            .any(|(channel, id)| match self.record_id(channel) {
+                Some(own) => own < *id,
+                None => true,
+            })
+    }
+
+    // stored spelling of channel
+    fn key(&self, channel: &str) -> Option<&str> {
+        self.channels
+            .keys()
+            .find(|c| c.eq_ignore_ascii_case(channel))
+            .map(|c| c.as_str())
+    }
+
+    // sets record id of channel, returns the stored spelling
+    fn insert(&mut self, channel: &str, record_id: u64) -> String {
+        let channel = self.key(channel).unwrap_or(channel).to_owned();
+        self.channels.insert(channel.clone(), record_id);
+        channel
+    }
+}
+
+impl fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_xml())
+    }
+}
+
+impl FromStr for Bookmark {
+    type Err = Error;
+
+    fn from_str(xml: &str) -> Result<Self> {
+        Bookmark::parse(xml)
+    }
+}
+
+fn bookmark_error(message: &str) -> Error {
+    Error {
+        kind: ErrorKind::Bookmark,
+        message: format!("invalid bookmark - {}", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const XML: &str = "<BookmarkList>\
+        <Bookmark Channel='Application' RecordId='6672'/>\
+        <Bookmark Channel='Security' RecordId='30044' IsCurrent='true'/>\
+        </BookmarkList>";
+
+    fn bookmark(channels: &[(&str, u64)]) -> Bookmark {
+        let mut bookmark = Bookmark::new();
+        for (channel, id) in channels {
+            bookmark.set(channel, *id);
+        }
+        bookmark
+    }
+
+    #[test]
+    fn parses_xml() {
+        let bookmark = Bookmark::parse(XML).unwrap();
+        assert_eq!(bookmark.record_id("Application"), Some(6672));
+        assert_eq!(bookmark.record_id("Security"), Some(30044));
+        assert_eq!(bookmark.current(), Some("Security"));
+        assert_eq!(bookmark.to_xml(), XML);
+        assert_eq!(XML.parse::<Bookmark>().unwrap(), bookmark);
+
+        let empty = Bookmark::parse("<BookmarkList/>").unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.current(), None);
+        assert_eq!(empty.to_xml(), "<BookmarkList></BookmarkList>");
+
+        let error = |xml: &str| {
+            let error = Bookmark::parse(xml).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Bookmark);
+            error.message
+        };
+        assert_eq!(
+            error("<Bookmark Channel='Security' RecordId='1'/>"),
+            "invalid bookmark - missing BookmarkList element"
+        );
+        assert_eq!(
+            error("<BookmarkList><Bookmark Channel='Security' RecordId='x'/></BookmarkList>"),
+            "invalid bookmark - invalid record id 'x'"
+        );
+        assert_eq!(
+            error("<BookmarkList><Bookmark RecordId='1'/></BookmarkList>"),
+            "invalid bookmark - bookmark without channel or record id"
+        );
+        assert_eq!(
+            error("<BookmarkList><Other/></BookmarkList>"),
+            "invalid bookmark - unexpected element 'Other'"
+        );
+        assert_eq!(
+            error("").as_str(),
+            "invalid bookmark - missing BookmarkList element"
+        );
+    }
+
+    #[test]
+    fn escapes_channel() {
+        let bookmark = bookmark(&[("Tom's <App> & \"Co\"", 7)]);
+        let xml = bookmark.to_xml();
+        assert_eq!(
+            xml,
+            "<BookmarkList><Bookmark Channel='Tom&apos;s &lt;App&gt; &amp; &quot;Co&quot;' \
+             RecordId='7' IsCurrent='true'/></BookmarkList>"
+        );
+        assert_eq!(Bookmark::parse(&xml).unwrap(), bookmark);
+    }
+
+    #[test]
+    fn sets_and_advances() {
+        let mut bookmark = bookmark(&[("Application", 5), ("Security", 9)]);
+        assert_eq!(bookmark.current(), Some("Security"));
+
+        bookmark.set("Application", 3);
+        assert_eq!(bookmark.record_id("Application"), Some(3));
+        assert_eq!(bookmark.current(), Some("Application"));
+
+        bookmark.advance(&Event {
+            channel: "System".to_owned(),
+            record_id: 12,
+            ..Default::default()
+        });
+        assert_eq!(bookmark.record_id("System"), Some(12));
+        assert_eq!(bookmark.current(), Some("System"));
+
+        let xml = "<Event><System><Provider Name='p'/><EventID>1</EventID><Version>0</Version>\
+            <Level>0</Level><Task>0</Task><Opcode>0</Opcode><Keywords>0x0</Keywords>\
+            <TimeCreated/><EventRecordID>40</EventRecordID><Correlation/>\
+            <Execution ProcessID='1' ThreadID='1'/><Channel>Security</Channel>\
+            <Computer>c</Computer><Security/></System></Event>";
+        bookmark.advance_raw(&RawEvent::try_from(xml.to_owned()).unwrap());
+        assert_eq!(bookmark.record_id("Security"), Some(40));
+        assert_eq!(bookmark.current(), Some("Security"));
+
+        assert_eq!(bookmark.remove("Security"), Some(40));
+        assert_eq!(bookmark.current(), None);
+        assert_eq!(bookmark.remove("Security"), None);
+        let channels: Vec<(&str, u64)> = bookmark.channels().collect();
+        assert_eq!(channels, vec![("Application", 3), ("System", 12)]);
+    }
+
+    #[test]
+    fn merges_furthest() {
+        let mut own = bookmark(&[("Application", 5), ("Security", 9)]);
+        own.current = None;
+        let other = bookmark(&[("Security", 4), ("System", 2), ("Application", 8)]);
+        assert!(own.is_behind(&other));
+        // both are further in some channel
+        assert!(other.is_behind(&own));
+
+        own.merge(&other);
+        let channels: Vec<(&str, u64)> = own.channels().collect();
+        assert_eq!(
+            channels,
+            vec![("Application", 8), ("Security", 9), ("System", 2)]
+        );
+        assert_eq!(own.current(), Some("Application"));
+        assert!(!own.is_behind(&other));
+        assert!(!own.is_behind(&own.clone()));
+        assert!(!own.is_behind(&Bookmark::new()));
+        assert!(Bookmark::new().is_behind(&own));
+
+        // current of self is kept
+        own.merge(&bookmark(&[("System", 1)]));
+        assert_eq!(own.current(), Some("Application"));
+    }
+
+    #[test]
+    fn channels_ignore_case() {
+        let mut bookmark = bookmark(&[("Security", 9)]);
+        assert_eq!(bookmark.record_id("security"), Some(9));
+
+        bookmark.set("SECURITY", 10);
+        let channels: Vec<(&str, u64)> = bookmark.channels().collect();
+        assert_eq!(channels, vec![("Security", 10)]);
+        assert_eq!(bookmark.current(), Some("Security"));
+
+        let other = Bookmark::parse(
+            "<BookmarkList><Bookmark Channel='security' RecordId='11' IsCurrent='true'/>\
+             <Bookmark Channel='SECURITY' RecordId='12'/></BookmarkList>",
+        )
+        .unwrap();
+        assert_eq!(
+            other.to_xml(),
+            "<BookmarkList><Bookmark Channel='security' \
+             RecordId='12' IsCurrent='true'/></BookmarkList>"
+        );
+        assert!(bookmark.is_behind(&other));
+
+        bookmark.merge(&other);
+        let channels: Vec<(&str, u64)> = bookmark.channels().collect();
+        assert_eq!(channels, vec![("Security", 12)]);
+        assert!(!bookmark.is_behind(&other));
+
+        assert_eq!(bookmark.remove("SeCuRiTy"), Some(12));
+        assert_eq!(bookmark.current(), None);
+        assert!(bookmark.is_empty());
+    }
+}
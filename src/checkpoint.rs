@@ -0,0 +1,482 @@
+use crate::bookmark::Bookmark;
+use crate::error::{Error, ErrorKind, Result};
+use crate::event::WinLogEvent;
+use crate::source::EventSource;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// numbers temporary files of saves in this process
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// BookmarkStore persists bookmark between runs
+pub trait BookmarkStore {
+    // stored bookmark, None when nothing was saved yet
+    fn load(&self) -> Result<Option<Bookmark>>;
+
+    fn save(&mut self, bookmark: &Bookmark) -> Result<()>;
+}
+
+// FileStore keeps bookmark xml in a file. New bookmark is written to a
+// temporary file next to it and renamed over the old one, so a crash during
+// save never leaves a partially written bookmark. Each save has its own
+// temporary file, stores of the same path never write into each other's.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // <name>.<pid>.<counter>.tmp
+    fn temp_path(&self) -> PathBuf {
+        let mut name = self
+            .path
+            .file_name()
+            .map(|n| n.to_os_string())
+            .unwrap_or_default();
+        name.push(format!(
+            ".{}.{}.tmp",
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        self.path.with_file_name(name)
+    }
+}
+
+impl BookmarkStore for FileStore {
+    fn load(&self) -> Result<Option<Bookmark>> {
+        match fs::read_to_string(&self.path) {
+            Ok(xml) if xml.trim().is_empty() => Ok(None),
+            Ok(xml) => Ok(Some(Bookmark::parse(&xml)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::bookmark("unable to read bookmark file", err)),
+        }
+    }
+
+    fn save(&mut self, bookmark: &Bookmark) -> Result<()> {
+        let temp = self.temp_path();
+
+        let write = || -> io::Result<()> {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+            file.write_all(bookmark.to_xml().as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp, &self.path)
+        };
+
+        write().map_err(|err| {
+            let _ = fs::remove_file(&temp);
+            Error::bookmark("unable to write bookmark file", err)
+        })?;
+
+        // rename is durable once the directory entry is flushed
+        sync_dir(&self.path)
+            .map_err(|err| Error::bookmark("unable to sync bookmark directory", err))
+    }
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+// windows can't open a directory as file, rename there is flushed with the
+// file system metadata
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// MemoryStore keeps bookmark in memory, useful for tests
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    bookmark: Option<Bookmark>,
+}
+
+impl MemoryStore {
+    pub fn new(bookmark: Option<Bookmark>) -> Self {
+        Self { bookmark }
+    }
+}
+
+impl BookmarkStore for MemoryStore {
+    fn load(&self) -> Result<Option<Bookmark>> {
+        Ok(self.bookmark.clone())
+    }
+
+    fn save(&mut self, bookmark: &Bookmark) -> Result<()> {
+        self.bookmark = Some(bookmark.clone());
+        Ok(())
+    }
+}
+
+// Policy decides when source bookmark is saved to store
+#[derive(Debug, Clone)]
+pub struct Policy {
+    // save after given number of events
+    pub every_events: Option<u64>,
+    // save when given time passed since last save
+    pub every: Option<Duration>,
+    // save when source is closed or dropped
+    pub on_close: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            every_events: None,
+            every: None,
+            on_close: true,
+        }
+    }
+}
+
+// Schedule tracks policy state between saves
+#[derive(Debug)]
+pub(crate) struct Schedule {
+    policy: Policy,
+    pending: u64,
+    last_saved: Instant,
+}
+
+impl Schedule {
+    pub(crate) fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            pending: 0,
+            last_saved: Instant::now(),
+        }
+    }
+
+    // adds handled events, true when save is due
+    pub(crate) fn record(&mut self, count: u64) -> bool {
+        self.pending += count;
+
+        if self.pending == 0 {
+            return false;
+        }
+
+        let by_count = match self.policy.every_events {
+            Some(n) => self.pending >= n,
+            None => false,
+        };
+        let by_time = match self.policy.every {
+            Some(t) => self.last_saved.elapsed() >= t,
+            None => false,
+        };
+
+        by_count || by_time
+    }
+
+    pub(crate) fn saved(&mut self) {
+        self.pending = 0;
+        self.last_saved = Instant::now();
+    }
+
+    pub(crate) fn due_on_close(&self) -> bool {
+        self.policy.on_close && self.pending > 0
+    }
+}
+
+// Checkpointer wraps a source and saves its bookmark to store as configured
+// by policy. Bookmark is saved after events are returned to the caller, a
+// failed save never drops events, it is kept in save_error and retried.
+pub struct Checkpointer<S: EventSource, B: BookmarkStore> {
+    source: S,
+    store: B,
+    schedule: Schedule,
+    save_error: Option<Error>,
+    closed: bool,
+}
+
+impl<S: EventSource, B: BookmarkStore> Checkpointer<S, B> {
+    pub fn new(source: S, store: B, policy: Policy) -> Self {
+        Self {
+            source,
+            store,
+            schedule: Schedule::new(policy),
+            save_error: None,
+            closed: false,
+        }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn store(&self) -> &B {
+        &self.store
+    }
+
+    // saves current bookmark of source
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let bookmark = self.source.bookmark()?;
+        self.store.save(&bookmark)?;
+        self.schedule.saved();
+        self.save_error = None;
+        Ok(())
+    }
+
+    // error of the last policy save, None once a save succeeds
+    pub fn save_error(&self) -> Option<&Error> {
+        self.save_error.as_ref()
+    }
+
+    // events are already taken from source when they are recorded, failed
+    // save stays due and is retried with the next call
+    fn record(&mut self, count: u64) {
+        if self.schedule.record(count) {
+            if let Err(err) = self.checkpoint() {
+                self.save_error = Some(err);
+            }
+        }
+    }
+}
+
+impl<S: EventSource, B: BookmarkStore> EventSource for Checkpointer<S, B> {
+    fn next_event(&mut self) -> Result<WinLogEvent> {
+        match self.source.next_event() {
+            Ok(event) => {
+                self.record(1);
+                Ok(event)
+            }
+            Err(err) => {
+                // time based checkpoint still runs while source is idle
+                if err.kind == ErrorKind::NoMoreLogs {
+                    self.record(0);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn next_batch(&mut self, max: usize) -> Result<Vec<Result<WinLogEvent>>> {
+        match self.source.next_batch(max) {
+            Ok(events) => {
+                self.record(events.iter().filter(|e| e.is_ok()).count() as u64);
+                Ok(events)
+            }
+            Err(err) => {
+                if err.kind == ErrorKind::NoMoreLogs {
+                    self.record(0);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn bookmark(&self) -> Result<Bookmark> {
+        self.source.bookmark()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        let saved = if self.schedule.due_on_close() {
+            self.checkpoint()
+        } else {
+            Ok(())
+        };
+
+        self.source.close()?;
+        saved
+    }
+
+    fn wait(&mut self, timeout: Duration) {
+        self.source.wait(timeout)
+    }
+}
+
+impl<S: EventSource, B: BookmarkStore> Drop for Checkpointer<S, B> {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use crate::source::MemorySource;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // store failing while broken is set, saved bookmarks are shared with test
+    #[derive(Default, Clone)]
+    struct FlakyStore {
+        broken: Rc<RefCell<bool>>,
+        saved: Rc<RefCell<Vec<Bookmark>>>,
+    }
+
+    impl BookmarkStore for FlakyStore {
+        fn load(&self) -> Result<Option<Bookmark>> {
+            Ok(self.saved.borrow().last().cloned())
+        }
+
+        fn save(&mut self, bookmark: &Bookmark) -> Result<()> {
+            if *self.broken.borrow() {
+                return Err(Error {
+                    kind: ErrorKind::File,
+                    message: "disk full".to_owned(),
+                });
+            }
+            self.saved.borrow_mut().push(bookmark.clone());
+            Ok(())
+        }
+    }
+
+    fn source(count: u64) -> MemorySource {
+        MemorySource::new(
+            (1..=count)
+                .map(|record_id| {
+                    WinLogEvent::Parsed(Event {
+                        channel: "Security".to_owned(),
+                        record_id,
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn every(n: u64) -> Policy {
+        Policy {
+            every_events: Some(n),
+            ..Default::default()
+        }
+    }
+
+    fn saved_ids(store: &FlakyStore) -> Vec<Option<u64>> {
+        store
+            .saved
+            .borrow()
+            .iter()
+            .map(|b| b.record_id("Security"))
+            .collect()
+    }
+
+    // empty directory of the test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("win_events_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_store_round_trip() {
+        let dir = test_dir("round_trip");
+        let path = dir.join("bookmark.xml");
+        let mut store = FileStore::new(&path);
+
+        let mut bookmark = Bookmark::new();
+        bookmark.set("Application", 3);
+        bookmark.set("Security", 7);
+        store.save(&bookmark).unwrap();
+        assert_eq!(store.load().unwrap(), Some(bookmark.clone()));
+
+        bookmark.set("Security", 8);
+        store.save(&bookmark).unwrap();
+        assert_eq!(FileStore::new(&path).load().unwrap(), Some(bookmark));
+
+        // temporary files are renamed away
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["bookmark.xml"]);
+
+        assert_ne!(store.temp_path(), store.temp_path());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_missing_or_empty() {
+        let dir = test_dir("missing");
+        let path = dir.join("bookmark.xml");
+        assert_eq!(FileStore::new(&path).load().unwrap(), None);
+
+        fs::write(&path, " \n").unwrap();
+        assert_eq!(FileStore::new(&path).load().unwrap(), None);
+
+        fs::write(&path, "<Bookmark/>").unwrap();
+        let err = FileStore::new(&path).load().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Bookmark);
+
+        // missing directory fails the save and leaves nothing behind
+        let mut store = FileStore::new(dir.join("none").join("bookmark.xml"));
+        let err = store.save(&Bookmark::new()).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Bookmark);
+        assert!(!dir.join("none").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_every_events() {
+        let store = FlakyStore::default();
+        let mut c = source(5).checkpointed(store.clone(), every(2));
+
+        for _ in 0..5 {
+            c.next_event().unwrap();
+        }
+        assert_eq!(saved_ids(&store), vec![Some(2), Some(4)]);
+
+        c.close().unwrap();
+        assert_eq!(saved_ids(&store), vec![Some(2), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn failed_save_keeps_event_and_retries() {
+        let store = FlakyStore::default();
+        *store.broken.borrow_mut() = true;
+        let mut c = source(3).checkpointed(store.clone(), every(1));
+
+        assert!(c.next_event().is_ok());
+        assert_eq!(c.save_error().unwrap().message, "disk full");
+        assert!(saved_ids(&store).is_empty());
+
+        *store.broken.borrow_mut() = false;
+        assert!(c.next_event().is_ok());
+        assert!(c.save_error().is_none());
+        assert_eq!(saved_ids(&store), vec![Some(2)]);
+    }
+
+    #[test]
+    fn failed_save_keeps_batch() {
+        let store = FlakyStore::default();
+        *store.broken.borrow_mut() = true;
+        let mut c = source(3).checkpointed(store.clone(), every(2));
+
+        let batch = c.next_batch(10).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(c.save_error().is_some());
+
+        // save stays due and is retried on close
+        *store.broken.borrow_mut() = false;
+        c.close().unwrap();
+        assert_eq!(saved_ids(&store), vec![Some(3)]);
+    }
+}
@@ -14,6 +14,10 @@ impl fmt::Display for Error {
         match self.kind {
             ErrorKind::Event => write!(f, "{}", self.message),
             ErrorKind::Subscription => write!(f, "{}", self.message),
+            ErrorKind::File => write!(f, "{}", self.message),
+            ErrorKind::Bookmark => write!(f, "{}", self.message),
+            ErrorKind::Query => write!(f, "{}", self.message),
+            ErrorKind::Filter => write!(f, "{}", self.message),
             _ => write!(f, "{}", self.kind),
         }
     }
@@ -24,6 +28,10 @@ pub enum ErrorKind {
     Event,
     Subscription,
     NoMoreLogs,
+    File,
+    Bookmark,
+    Query,
+    Filter,
     // XmlParseError,
 }
 
@@ -33,6 +41,10 @@ impl fmt::Display for ErrorKind {
             ErrorKind::Event => "event error",
             ErrorKind::Subscription => "event subscription error",
             ErrorKind::NoMoreLogs => "no more logs to pull",
+            ErrorKind::File => "event log file error",
+            ErrorKind::Bookmark => "invalid bookmark",
+            ErrorKind::Query => "invalid query",
+            ErrorKind::Filter => "invalid filter",
             // ErrorKind::XmlParseError => "error parsing xml event",
         };
 
@@ -41,6 +53,7 @@ impl fmt::Display for ErrorKind {
 }
 
 impl Error {
+    #[cfg(all(target_os = "windows", feature = "reader"))]
     pub(crate) fn event(message: &str, error: io::Error) -> Self {
         Error {
             kind: ErrorKind::Event,
@@ -48,10 +61,25 @@ impl Error {
         }
     }
 
+    #[cfg(all(target_os = "windows", feature = "reader"))]
     pub(crate) fn subscription(message: &str, error: io::Error) -> Self {
         Error {
             kind: ErrorKind::Subscription,
             message: format!("{} - ({})", message, error),
         }
     }
+
+    pub(crate) fn file(message: &str, error: io::Error) -> Self {
+        Error {
+            kind: ErrorKind::File,
+            message: format!("{} - ({})", message, error),
+        }
+    }
+
+    pub(crate) fn bookmark(message: &str, error: io::Error) -> Self {
+        Error {
+            kind: ErrorKind::Bookmark,
+            message: format!("{} - ({})", message, error),
+        }
+    }
 }
@@ -1,13 +1,18 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use core::convert::Into;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as SerdeValue;
 use std::collections::HashMap;
 use std::convert::From;
 use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::fmt;
 
+use crate::binary::{decode_hex, BinaryData};
 use crate::error::{Error, ErrorKind};
 
+use quick_xml::{events::BytesStart, events::Event as QuickXmlEvent, Reader as QuickXmlReader};
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum WinLogEvent {
     Xml(String),
@@ -16,12 +21,47 @@ pub enum WinLogEvent {
     Json(String),
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum Output {
+    Xml,
+    Raw,
+    Parsed,
+    Json,
+}
+
+impl WinLogEvent {
+    // convert rendered event xml into requested output format
+    pub fn from_xml(xml: String, output: Output) -> Result<Self, Error> {
+        match output {
+            Output::Xml => Ok(WinLogEvent::Xml(xml)),
+
+            Output::Raw => Ok(WinLogEvent::Raw(xml.try_into()?)),
+
+            Output::Parsed => Ok(WinLogEvent::Parsed(
+                TryInto::<RawEvent>::try_into(xml)?.into(),
+            )),
+
+            Output::Json => {
+                let event: Event = TryInto::<RawEvent>::try_into(xml)?.into();
+                match serde_json::to_string(&event) {
+                    Ok(json) => Ok(WinLogEvent::Json(json)),
+                    Err(err) => Err(Error {
+                        kind: ErrorKind::Event,
+                        message: err.to_string(),
+                    }),
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct RawEvent {
     pub system: System,
     pub event_data: Option<EventData>,
-    pub user_data: Option<HashMap<String, UserData>>,
+    // UserData element, payload differs per provider
+    pub user_data: Option<Element>,
     pub rendering_info: Option<RenderingInfo>,
 }
 
@@ -119,7 +159,310 @@ pub struct Data {
     pub value: Option<String>,
 }
 
-pub type UserData = HashMap<String, String>;
+// DataValue is EventData value typed by the Type attribute of Data, values
+// without type, with unknown type or which don't parse are kept as String.
+// Json is untagged so reading it back is lossy: Guid and Sid come back as
+// String, strings looking like RFC 3339 times as Time and positive Int as
+// UInt. Json has no NaN or infinity, such floats are kept as String.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum DataValue {
+    Boolean(bool),
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    Time(DateTime<Utc>),
+    String(String),
+    Guid(String),
+    Sid(String),
+}
+
+impl DataValue {
+    // value of Data with type like win:UInt32, win prefix is optional
+    pub fn parse(value: &str, data_type: Option<&str>) -> Self {
+        let data_type = data_type.unwrap_or_default();
+        let data_type = data_type.strip_prefix("win:").unwrap_or(data_type);
+        let v = value.trim();
+
+        let typed = match data_type {
+            "Int8" | "Int16" | "Int32" | "Int64" => v.parse().ok().map(DataValue::Int),
+            "UInt8" | "UInt16" | "UInt32" | "UInt64" => v.parse().ok().map(DataValue::UInt),
+            "HexInt8" | "HexInt16" | "HexInt32" | "HexInt64" | "Pointer" => {
+                let hex = v.trim_start_matches("0x").trim_start_matches("0X");
+                u64::from_str_radix(hex, 16).ok().map(DataValue::UInt)
+            }
+            "Float" | "Double" => v
+                .parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite())
+                .map(DataValue::Float),
+            "Boolean" => match v {
+                "true" | "1" => Some(DataValue::Boolean(true)),
+                "false" | "0" => Some(DataValue::Boolean(false)),
+                _ => None,
+            },
+            "FILETIME" | "SYSTEMTIME" => DateTime::parse_from_rfc3339(v)
+                .ok()
+                .map(|t| DataValue::Time(t.with_timezone(&Utc))),
+            "GUID" => Some(DataValue::Guid(v.to_owned())),
+            "SID" => Some(DataValue::Sid(v.to_owned())),
+            _ => None,
+        };
+
+        typed.unwrap_or_else(|| DataValue::String(value.to_owned()))
+    }
+}
+
+impl fmt::Display for DataValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataValue::Boolean(v) => write!(f, "{}", v),
+            DataValue::UInt(v) => write!(f, "{}", v),
+            DataValue::Int(v) => write!(f, "{}", v),
+            DataValue::Float(v) => write!(f, "{}", v),
+            DataValue::Time(v) => write!(f, "{}", v.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            DataValue::String(v) | DataValue::Guid(v) | DataValue::Sid(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+// DataItem is EventData Data element in document order, index is its
+// position and message inserts %1 are index 0, duplicate names are kept
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DataItem {
+    pub index: usize,
+    pub name: Option<String>,
+    pub value: Option<DataValue>,
+}
+
+// Element is generic xml element, UserData payloads nest elements, repeat
+// them and carry attributes. Serde can't map them to a fixed type, event
+// xml is read with its own reader.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub text: String,
+    pub children: Vec<Element>,
+}
+
+// UserDataFormat is how UserData tree is flattened into Event user_data,
+// paths start below the payload element, UserData/LogFileCleared/Channel
+// is Channel
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum UserDataFormat {
+    // leaf element name to text, of repeated names the last one is kept
+    #[default]
+    Flat,
+    // dotted path to text, repeated elements get their index and attributes
+    // an @ segment, Rules.Rule.0.@Id and Rules.Rule.0.Name
+    Dotted,
+    // json tree, repeated elements are arrays, attributes are @ keys and
+    // text of element with attributes or children is #text
+    Nested,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // concatenated text of element and its descendants
+    pub fn value(&self) -> String {
+        let mut value = self.text.clone();
+        for child in &self.children {
+            value.push_str(&child.value());
+        }
+        value
+    }
+
+    // fields of payload elements, self is UserData element
+    pub fn flatten(&self, format: UserDataFormat) -> HashMap<String, SerdeValue> {
+        let mut fields = HashMap::new();
+
+        for payload in &self.children {
+            match format {
+                UserDataFormat::Flat => payload.flatten_leaves(&mut fields),
+                UserDataFormat::Dotted => payload.flatten_paths("", &mut fields),
+                UserDataFormat::Nested => {
+                    if let SerdeValue::Object(map) = payload.nested() {
+                        fields.extend(map);
+                    }
+                }
+            }
+        }
+
+        fields
+    }
+
+    fn flatten_leaves(&self, fields: &mut HashMap<String, SerdeValue>) {
+        for child in &self.children {
+            if child.children.is_empty() {
+                fields.insert(child.name.clone(), SerdeValue::String(child.text.clone()));
+            } else {
+                child.flatten_leaves(fields);
+            }
+        }
+    }
+
+    fn flatten_paths(&self, path: &str, fields: &mut HashMap<String, SerdeValue>) {
+        let join = |name: &str| match path {
+            "" => name.to_owned(),
+            _ => format!("{}.{}", path, name),
+        };
+
+        for (name, value) in self.data_attributes() {
+            fields.insert(
+                join(&format!("@{}", name)),
+                SerdeValue::String(value.clone()),
+            );
+        }
+
+        if !path.is_empty() && (self.children.is_empty() || !self.text.is_empty()) {
+            let key = match self.children.is_empty() {
+                true => path.to_owned(),
+                false => join("#text"),
+            };
+            fields.insert(key, SerdeValue::String(self.text.clone()));
+        }
+
+        for (i, child) in self.children.iter().enumerate() {
+            let repeated = self
+                .children
+                .iter()
+                .filter(|c| c.name == child.name)
+                .count()
+                > 1;
+
+            let child_path = match repeated {
+                true => {
+                    let index = self.children[..i]
+                        .iter()
+                        .filter(|c| c.name == child.name)
+                        .count();
+                    join(&format!("{}.{}", child.name, index))
+                }
+                false => join(&child.name),
+            };
+            child.flatten_paths(&child_path, fields);
+        }
+    }
+
+    fn nested(&self) -> SerdeValue {
+        if self.data_attributes().next().is_none() && self.children.is_empty() {
+            return SerdeValue::String(self.text.clone());
+        }
+
+        let mut map = serde_json::Map::new();
+        for (name, value) in self.data_attributes() {
+            map.insert(format!("@{}", name), SerdeValue::String(value.clone()));
+        }
+        if !self.text.is_empty() {
+            map.insert("#text".into(), SerdeValue::String(self.text.clone()));
+        }
+
+        for child in &self.children {
+            let value = child.nested();
+            match map.get_mut(&child.name) {
+                Some(SerdeValue::Array(values)) => values.push(value),
+                Some(first) => *first = SerdeValue::Array(vec![first.take(), value]),
+                None => {
+                    map.insert(child.name.clone(), value);
+                }
+            }
+        }
+
+        SerdeValue::Object(map)
+    }
+
+    // attributes without namespace declarations
+    fn data_attributes(&self) -> impl Iterator<Item = &(String, String)> {
+        self.attributes
+            .iter()
+            .filter(|(n, _)| n != "xmlns" && !n.starts_with("xmlns:"))
+    }
+
+    fn start(e: &BytesStart, reader: &QuickXmlReader<&[u8]>) -> Result<Self, Error> {
+        let mut element = Element {
+            name: String::from_utf8_lossy(e.name()).into_owned(),
+            ..Default::default()
+        };
+
+        for a in e.attributes() {
+            let att = a.map_err(|e| event_error(&e.to_string()))?;
+            let value = att
+                .unescape_and_decode_value(reader)
+                .map_err(|e| event_error(&e.to_string()))?;
+            element
+                .attributes
+                .push((String::from_utf8_lossy(att.key).into_owned(), value));
+        }
+
+        Ok(element)
+    }
+}
+
+// UserData element of event xml
+fn parse_user_data(xml: &str) -> Result<Option<Element>, Error> {
+    let mut reader = QuickXmlReader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut depth = 0;
+    // open elements from UserData down
+    let mut stack: Vec<Element> = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(QuickXmlEvent::Start(ref e)) => {
+                depth += 1;
+                if !stack.is_empty() || (depth == 2 && e.name() == b"UserData") {
+                    stack.push(Element::start(e, &reader)?);
+                }
+            }
+            Ok(QuickXmlEvent::Empty(ref e)) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(Element::start(e, &reader)?);
+                } else if depth == 1 && e.name() == b"UserData" {
+                    return Ok(Some(Element::start(e, &reader)?));
+                }
+            }
+            Ok(QuickXmlEvent::Text(ref t)) | Ok(QuickXmlEvent::CData(ref t)) => {
+                if let Some(element) = stack.last_mut() {
+                    let text = t
+                        .unescape_and_decode(&reader)
+                        .map_err(|e| event_error(&e.to_string()))?;
+                    element.text.push_str(&text);
+                }
+            }
+            Ok(QuickXmlEvent::End(_)) => {
+                depth -= 1;
+                if let Some(element) = stack.pop() {
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(Some(element)),
+                    }
+                }
+            }
+            Ok(QuickXmlEvent::Eof) => return Ok(None),
+            Err(e) => return Err(event_error(&e.to_string())),
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+fn event_error(message: &str) -> Error {
+    Error {
+        kind: ErrorKind::Event,
+        message: message.to_owned(),
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "PascalCase")]
@@ -144,7 +487,10 @@ impl TryFrom<String> for RawEvent {
 
     fn try_from(xml: String) -> Result<Self, Error> {
         match quick_xml::de::from_str::<RawEvent>(&xml) {
-            Ok(e) => Ok(e),
+            Ok(mut e) => {
+                e.user_data = parse_user_data(&xml)?;
+                Ok(e)
+            }
             Err(err) => Err(Error {
                 kind: ErrorKind::Event,
                 message: err.to_string(),
@@ -154,7 +500,8 @@ impl TryFrom<String> for RawEvent {
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
-#[serde(rename_all = "camelCase")]
+// missing fields are defaulted, json written before they were added still reads
+#[serde(default, rename_all = "camelCase")]
 pub struct Event {
     pub record_id: u64,
     pub provider_name: String,
@@ -163,59 +510,105 @@ pub struct Event {
     pub system_time: Option<DateTime<Utc>>,
 
     pub event_id: u32,
+    pub qualifiers: Option<u16>,
+    pub version: u8,
     pub computer_name: String,
     pub activity_id: String,
+    pub related_activity_id: String,
     pub channel: String,
+
+    // rendered names and numeric values from System
     pub level: String,
+    pub level_number: u8,
     pub opcode: String,
-
+    pub opcode_number: u8,
     pub task: String,
+    pub task_number: u16,
     pub message: String,
 
     pub process_id: u32,
     pub thread_id: u32,
+    pub processor_id: Option<u8>,
+    pub session_id: Option<u32>,
+    pub kernel_time: Option<u32>,
+    pub user_time: Option<u32>,
+    pub processor_time: Option<u32>,
 
     pub user: HashMap<String, SerdeValue>,
 
     pub keywords: Vec<String>,
+    pub keywords_mask: u64,
 
-    pub event_data: HashMap<String, String>,
-    pub user_data: HashMap<String, String>,
+    pub event_data: HashMap<String, DataValue>,
+    // all Data elements of event_data in order
+    pub event_data_items: Vec<DataItem>,
+    // EventData Binary, also in event_data as hex, and its decoded layout
+    // for known providers. Json only has the hex, bytes would repeat it.
+    #[serde(skip)]
+    pub binary: Vec<u8>,
+    pub binary_data: Option<BinaryData>,
+    // UserData fields flattened with UserDataFormat
+    pub user_data: HashMap<String, SerdeValue>,
 }
 
 impl From<RawEvent> for Event {
     fn from(raw_event: RawEvent) -> Self {
-        let mut event: Event = Event::default();
+        Event::from_raw(raw_event, UserDataFormat::default())
+    }
+}
+
+impl Event {
+    pub fn from_raw(raw_event: RawEvent, user_data: UserDataFormat) -> Self {
+        let system = raw_event.system;
+        let execution = system.execution;
 
-        event.event_id = raw_event.system.event_id.id;
-        event.computer_name = raw_event.system.computer;
-        event.channel = raw_event.system.channel;
-        event.process_id = raw_event.system.execution.process_id;
-        event.thread_id = raw_event.system.execution.thread_id;
+        let mut event: Event = Event {
+            event_id: system.event_id.id,
+            qualifiers: system.event_id.qualifiers,
+            version: system.version,
+            computer_name: system.computer,
+            channel: system.channel,
+            level_number: system.level,
+            opcode_number: system.opcode,
+            task_number: system.task,
+            keywords_mask: u64::from_str_radix(system.keywords.trim_start_matches("0x"), 16)
+                .unwrap_or_default(),
+            process_id: execution.process_id,
+            thread_id: execution.thread_id,
+            processor_id: execution.processor_id,
+            session_id: execution.session_id,
+            kernel_time: execution.kernel_time,
+            user_time: execution.user_time,
+            processor_time: execution.processor_time,
+            ..Default::default()
+        };
 
-        if let Some(t) = raw_event.system.time_created.system_time {
+        if let Some(t) = system.time_created.system_time {
             if let Ok(t) = DateTime::parse_from_rfc3339(&t) {
                 event.system_time = Some(t.with_timezone(&Utc));
             }
         }
 
-        if let Some(id) = raw_event.system.event_record_id {
+        if let Some(id) = system.event_record_id {
             event.record_id = id;
         }
 
-        if let Some(name) = raw_event.system.provider.name {
+        if let Some(name) = system.provider.name {
             event.provider_name = name;
         }
-        if let Some(guid) = raw_event.system.provider.guid {
+        if let Some(guid) = system.provider.guid {
             event.provider_guid = guid;
         }
-        if let Some(name) = raw_event.system.provider.event_source_name {
+        if let Some(name) = system.provider.event_source_name {
             event.source_name = name;
         }
 
-        if let Some(id) = raw_event.system.correlation.activity_id {
+        if let Some(id) = system.correlation.activity_id {
             event.activity_id = id;
         }
+        if let Some(id) = system.correlation.related_activity_id {
+            event.related_activity_id = id;
+        }
 
         if let Some(rend_info) = raw_event.rendering_info {
             event.level = rend_info.level;
@@ -228,7 +621,26 @@ impl From<RawEvent> for Event {
             }
         }
 
-        if let Some(id) = raw_event.system.security.user_id {
+        // providers without metadata and exports without rendering info
+        // have no names, standard values are named from winmeta.xml
+        if event.level.is_empty() {
+            event.level = standard_name(LEVEL_NAMES, event.level_number);
+        }
+        if event.opcode.is_empty() {
+            event.opcode = standard_name(OPCODE_NAMES, event.opcode_number);
+        }
+        if event.task.is_empty() && event.task_number == 0 {
+            event.task = "None".into();
+        }
+        if event.keywords.is_empty() {
+            event.keywords = KEYWORD_NAMES
+                .iter()
+                .filter(|(mask, _)| event.keywords_mask & mask != 0)
+                .map(|(_, name)| name.to_string())
+                .collect();
+        }
+
+        if let Some(id) = system.security.user_id {
             event
                 .user
                 .insert("identifier".into(), SerdeValue::String(id));
@@ -238,31 +650,526 @@ impl From<RawEvent> for Event {
             // let mut event_data = HashMap::new();
 
             if let Some(data) = raw_event_data.data {
-                for (i, d) in data.iter().enumerate() {
-                    if d.value.is_some() {
+                for (i, d) in data.into_iter().enumerate() {
+                    let data_type = d.data_type.as_deref();
+                    let value = d.value.as_deref().map(|v| DataValue::parse(v, data_type));
+
+                    if let Some(value) = &value {
                         match &d.name {
-                            Some(n) => event
-                                .event_data
-                                .insert(n.to_string(), d.value.to_owned().unwrap()),
+                            Some(n) => event.event_data.insert(n.to_string(), value.clone()),
                             None => event
                                 .event_data
-                                .insert(format!("param{}", i), d.value.to_owned().unwrap()),
+                                .insert(format!("param{}", i), value.clone()),
                         };
                     }
+
+                    event.event_data_items.push(DataItem {
+                        index: i,
+                        name: d.name,
+                        value,
+                    });
                 }
             }
 
             if let Some(bin) = raw_event_data.binary {
-                event.event_data.insert("binary".into(), bin);
+                if let Some(bytes) = decode_hex(&bin) {
+                    event.binary_data = BinaryData::decode(&event.provider_name, &bytes);
+                    event.binary = bytes;
+                }
+                event
+                    .event_data
+                    .insert("binary".into(), DataValue::String(bin));
             }
         }
 
         if let Some(raw_user_data) = raw_event.user_data {
-            for (_, values) in raw_user_data {
-                event.user_data.extend(values);
-            }
+            event.user_data = raw_user_data.flatten(user_data);
         }
 
         event
     }
 }
+
+// rendered names of winmeta.xml levels, opcodes and reserved keywords
+// level 0 is LogAlways in winmeta.xml, windows renders it as Information
+const LEVEL_NAMES: &[(u8, &str)] = &[
+    (0, "Information"),
+    (1, "Critical"),
+    (2, "Error"),
+    (3, "Warning"),
+    (4, "Information"),
+    (5, "Verbose"),
+];
+
+const OPCODE_NAMES: &[(u8, &str)] = &[
+    (0, "Info"),
+    (1, "Start"),
+    (2, "Stop"),
+    (3, "DC_Start"),
+    (4, "DC_Stop"),
+    (5, "Extension"),
+    (6, "Reply"),
+    (7, "Resume"),
+    (8, "Suspend"),
+    (9, "Send"),
+    (240, "Receive"),
+];
+
+const KEYWORD_NAMES: &[(u64, &str)] = &[
+    (0x0001_0000_0000_0000, "Response Time"),
+    (0x0002_0000_0000_0000, "WDI Context"),
+    (0x0004_0000_0000_0000, "WDI Diag"),
+    (0x0008_0000_0000_0000, "SQM"),
+    (0x0010_0000_0000_0000, "Audit Failure"),
+    (0x0020_0000_0000_0000, "Audit Success"),
+    (0x0080_0000_0000_0000, "Classic"),
+];
+
+// provider defined values have no standard name and stay empty
+fn standard_name(names: &[(u8, &str)], value: u8) -> String {
+    names
+        .iter()
+        .find(|(v, _)| *v == value)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::event_position;
+
+    #[test]
+    fn parses_data_values() {
+        let cases = vec![
+            ("42", Some("win:UInt32"), DataValue::UInt(42)),
+            ("-42", Some("Int64"), DataValue::Int(-42)),
+            ("0x1F", Some("win:HexInt32"), DataValue::UInt(31)),
+            ("1.5", Some("win:Double"), DataValue::Float(1.5)),
+            ("1", Some("win:Boolean"), DataValue::Boolean(true)),
+            (
+                "S-1-5-18",
+                Some("win:SID"),
+                DataValue::Sid("S-1-5-18".into()),
+            ),
+            ("-1", Some("win:UInt32"), DataValue::String("-1".into())),
+            ("42", None, DataValue::String("42".into())),
+            ("42", Some("win:Unknown"), DataValue::String("42".into())),
+        ];
+
+        for (value, data_type, expected) in cases {
+            assert_eq!(DataValue::parse(value, data_type), expected, "{}", value);
+        }
+
+        let time = DataValue::parse("2021-03-01T10:00:00.123Z", Some("win:FILETIME"));
+        assert_eq!(time.to_string(), "2021-03-01T10:00:00.123Z");
+    }
+
+    #[test]
+    fn non_finite_floats_are_strings() {
+        for value in ["NaN", "inf", "-inf", "infinity", "1e999"].iter() {
+            assert_eq!(
+                DataValue::parse(value, Some("win:Float")),
+                DataValue::String(value.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let values = vec![
+            DataValue::Boolean(true),
+            DataValue::UInt(u64::MAX),
+            DataValue::Int(-1),
+            DataValue::Float(0.25),
+            DataValue::parse("2021-03-01T10:00:00Z", Some("win:SYSTEMTIME")),
+            DataValue::String("alice".into()),
+        ];
+        for value in values {
+            let json = serde_json::to_string(&value).unwrap();
+            assert_eq!(serde_json::from_str::<DataValue>(&json).unwrap(), value);
+        }
+
+        // documented losses of untagged json
+        let lossy = vec![
+            (
+                DataValue::Sid("S-1-5-18".into()),
+                DataValue::String("S-1-5-18".into()),
+            ),
+            (DataValue::Int(7), DataValue::UInt(7)),
+            (
+                DataValue::String("2021-03-01T10:00:00Z".into()),
+                DataValue::parse("2021-03-01T10:00:00Z", Some("win:SYSTEMTIME")),
+            ),
+        ];
+        for (value, read) in lossy {
+            let json = serde_json::to_string(&value).unwrap();
+            assert_eq!(serde_json::from_str::<DataValue>(&json).unwrap(), read);
+        }
+    }
+
+    #[test]
+    fn json_event_with_nan_reads_back() {
+        let xml = r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System>
+    <Provider Name='Sensor'/>
+    <EventID>1</EventID>
+    <Version>0</Version>
+    <Level>4</Level>
+    <Task>0</Task>
+    <Opcode>0</Opcode>
+    <Keywords>0x0</Keywords>
+    <TimeCreated SystemTime='2021-03-01T10:00:00.0000000Z'/>
+    <EventRecordID>7</EventRecordID>
+    <Correlation/>
+    <Execution ProcessID='1' ThreadID='2'/>
+    <Channel>Application</Channel>
+    <Computer>host</Computer>
+    <Security/>
+  </System>
+  <EventData>
+    <Data Name='Reading' Type='win:Float'>NaN</Data>
+  </EventData>
+</Event>"#;
+
+        let event = WinLogEvent::from_xml(xml.to_owned(), Output::Json).unwrap();
+        assert_eq!(event_position(&event), Some(("Application".to_owned(), 7)));
+
+        let json = match event {
+            WinLogEvent::Json(json) => json,
+            e => panic!("unexpected output {:?}", e),
+        };
+        let event: Event = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            event.event_data.get("Reading"),
+            Some(&DataValue::String("NaN".into()))
+        );
+    }
+
+    #[test]
+    fn binary_is_written_once() {
+        let xml = r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System>
+    <Provider Name='Service Control Manager'/>
+    <EventID Qualifiers='16384'>7036</EventID>
+    <Version>0</Version>
+    <Level>4</Level>
+    <Task>0</Task>
+    <Opcode>0</Opcode>
+    <Keywords>0x8080000000000000</Keywords>
+    <TimeCreated SystemTime='2021-03-01T10:00:00.0000000Z'/>
+    <EventRecordID>9</EventRecordID>
+    <Correlation/>
+    <Execution ProcessID='1' ThreadID='2'/>
+    <Channel>System</Channel>
+    <Computer>host</Computer>
+    <Security/>
+  </System>
+  <EventData>
+    <Data Name='param1'>Windows Update</Data>
+    <Binary>770075002F0034000000</Binary>
+  </EventData>
+</Event>"#;
+
+        let parsed = match WinLogEvent::from_xml(xml.to_owned(), Output::Parsed).unwrap() {
+            WinLogEvent::Parsed(e) => e,
+            e => panic!("unexpected output {:?}", e),
+        };
+        assert_eq!(parsed.binary.len(), 10);
+        assert_eq!(parsed.binary_data, Some(BinaryData::Text("wu/4".into())));
+
+        let json = serde_json::to_value(&parsed).unwrap();
+        assert!(json.get("binary").is_none());
+        assert_eq!(json["eventData"]["binary"], "770075002F0034000000");
+        assert_eq!(json["binaryData"]["Text"], "wu/4");
+    }
+
+    // event of Sensor provider without RenderingInfo
+    fn sensor_event(level: u8, opcode: u8, task: u16, keywords: &str, rendering: &str) -> Event {
+        let xml = format!(
+            r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System>
+    <Provider Name='Sensor'/>
+    <EventID>1</EventID>
+    <Version>0</Version>
+    <Level>{}</Level>
+    <Task>{}</Task>
+    <Opcode>{}</Opcode>
+    <Keywords>{}</Keywords>
+    <TimeCreated SystemTime='2021-03-01T10:00:00.0000000Z'/>
+    <EventRecordID>7</EventRecordID>
+    <Correlation/>
+    <Execution ProcessID='1' ThreadID='2'/>
+    <Channel>Application</Channel>
+    <Computer>host</Computer>
+    <Security/>
+  </System>
+  {}
+</Event>"#,
+            level, task, opcode, keywords, rendering
+        );
+        RawEvent::try_from(xml).unwrap().into()
+    }
+
+    #[test]
+    fn system_fields_from_raw_event() {
+        let xml = r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System>
+    <Provider Name='Service Control Manager' EventSourceName='Service Control Manager'/>
+    <EventID Qualifiers='49152'>7000</EventID>
+    <Version>2</Version>
+    <Level>2</Level>
+    <Task>17</Task>
+    <Opcode>12</Opcode>
+    <Keywords>0x8080000000000000</Keywords>
+    <TimeCreated SystemTime='2021-03-01T10:00:00.1234567Z'/>
+    <EventRecordID>6672</EventRecordID>
+    <Correlation ActivityID='{A1B2C3D4-0000-0000-0000-000000000001}' RelatedActivityID='{A1B2C3D4-0000-0000-0000-000000000002}'/>
+    <Execution ProcessID='812' ThreadID='4420' ProcessorID='3' SessionID='1' KernelTime='15' UserTime='30' ProcessorTime='45'/>
+    <Channel>System</Channel>
+    <Computer>host</Computer>
+    <Security UserID='S-1-5-18'/>
+  </System>
+</Event>"#;
+        let e = Event::from(RawEvent::try_from(xml.to_owned()).unwrap());
+
+        assert_eq!(e.event_id, 7000);
+        assert_eq!(e.qualifiers, Some(49152));
+        assert_eq!(e.version, 2);
+        assert_eq!(e.record_id, 6672);
+        assert_eq!(e.source_name, "Service Control Manager");
+        assert_eq!(
+            e.system_time
+                .unwrap()
+                .to_rfc3339_opts(SecondsFormat::Nanos, true),
+            "2021-03-01T10:00:00.123456700Z"
+        );
+
+        assert_eq!(e.activity_id, "{A1B2C3D4-0000-0000-0000-000000000001}");
+        assert_eq!(
+            e.related_activity_id,
+            "{A1B2C3D4-0000-0000-0000-000000000002}"
+        );
+
+        assert_eq!(e.process_id, 812);
+        assert_eq!(e.thread_id, 4420);
+        assert_eq!(e.processor_id, Some(3));
+        assert_eq!(e.session_id, Some(1));
+        assert_eq!(e.kernel_time, Some(15));
+        assert_eq!(e.user_time, Some(30));
+        assert_eq!(e.processor_time, Some(45));
+
+        assert_eq!(
+            e.user.get("identifier"),
+            Some(&SerdeValue::String("S-1-5-18".into()))
+        );
+
+        assert_eq!(e.level_number, 2);
+        assert_eq!(e.level, "Error");
+        assert_eq!(e.opcode_number, 12);
+        assert_eq!(e.task_number, 17);
+        assert_eq!(e.keywords_mask, 0x8080_0000_0000_0000);
+        assert_eq!(e.keywords, vec!["Classic"]);
+
+        // optional parts missing from the xml stay empty
+        let e = sensor_event(4, 0, 0, "0x0", "");
+        assert_eq!(e.activity_id, "");
+        assert_eq!(e.related_activity_id, "");
+        assert_eq!((e.process_id, e.thread_id), (1, 2));
+        assert_eq!(e.processor_id, None);
+        assert_eq!(e.session_id, None);
+        assert_eq!(e.kernel_time, None);
+        assert!(e.user.is_empty());
+        assert_eq!(e.keywords_mask, 0);
+        assert!(e.keywords.is_empty());
+    }
+
+    #[test]
+    fn event_data_items_keep_order() {
+        let e = sensor_event(
+            4,
+            0,
+            0,
+            "0x0",
+            "<EventData>\
+               <Data Name='Count' Type='win:UInt32'>3</Data>\
+               <Data>first unnamed</Data>\
+               <Data Name='User'>alice</Data>\
+               <Data Name='User'>bob</Data>\
+               <Data Name='Empty'/>\
+               <Data>second unnamed</Data>\
+             </EventData>",
+        );
+
+        let item = |index: usize, name: Option<&str>, value: Option<DataValue>| DataItem {
+            index,
+            name: name.map(|n| n.to_owned()),
+            value,
+        };
+        let text = |v: &str| Some(DataValue::String(v.to_owned()));
+        assert_eq!(
+            e.event_data_items,
+            vec![
+                item(0, Some("Count"), Some(DataValue::UInt(3))),
+                item(1, None, text("first unnamed")),
+                item(2, Some("User"), text("alice")),
+                item(3, Some("User"), text("bob")),
+                item(4, Some("Empty"), None),
+                item(5, None, text("second unnamed")),
+            ]
+        );
+
+        // the map keeps the last of duplicate names, unnamed data is keyed
+        // by position and empty data has no value
+        let mut names: Vec<&str> = e.event_data.keys().map(|k| k.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["Count", "User", "param1", "param5"]);
+        assert_eq!(e.event_data["User"], DataValue::String("bob".into()));
+        assert_eq!(e.event_data["Count"], DataValue::UInt(3));
+        assert_eq!(
+            e.event_data["param1"],
+            DataValue::String("first unnamed".into())
+        );
+
+        let e = sensor_event(4, 0, 0, "0x0", "");
+        assert!(e.event_data_items.is_empty());
+        assert!(e.event_data.is_empty());
+    }
+
+    #[test]
+    fn standard_names_without_rendering_info() {
+        let e = sensor_event(0, 1, 0, "0x80a0000000000000", "");
+        assert_eq!(e.level, "Information");
+        assert_eq!(e.opcode, "Start");
+        assert_eq!(e.task, "None");
+        assert_eq!(e.keywords, vec!["Audit Success", "Classic"]);
+
+        let e = sensor_event(2, 240, 0, "0x10000000000000", "");
+        assert_eq!((e.level.as_str(), e.opcode.as_str()), ("Error", "Receive"));
+        assert_eq!(e.keywords, vec!["Audit Failure"]);
+
+        // provider defined values have no standard name
+        let e = sensor_event(16, 77, 3, "0x40000000000001", "");
+        assert_eq!((e.level.as_str(), e.opcode.as_str()), ("", ""));
+        assert_eq!(e.task, "");
+        assert!(e.keywords.is_empty());
+        assert_eq!(
+            (e.level_number, e.opcode_number, e.task_number),
+            (16, 77, 3)
+        );
+    }
+
+    #[test]
+    fn rendering_info_names_are_kept() {
+        let e = sensor_event(
+            0,
+            1,
+            3,
+            "0x8020000000000000",
+            "<RenderingInfo Culture='en-US'><Message>m</Message><Level>Lowest</Level>\
+             <Task>Probe</Task><Opcode>Begin</Opcode><Channel>Application</Channel>\
+             <Provider>Sensor</Provider><Keywords><Keyword>Mine</Keyword></Keywords>\
+             </RenderingInfo>",
+        );
+        assert_eq!(
+            (e.level.as_str(), e.opcode.as_str(), e.task.as_str()),
+            ("Lowest", "Begin", "Probe")
+        );
+        assert_eq!(e.keywords, vec!["Mine"]);
+        assert_eq!(e.message, "m");
+    }
+
+    const USER_DATA: &str = r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System><Channel>Security</Channel></System>
+  <UserData>
+    <LogFileCleared xmlns='http://manifests.microsoft.com/win/2004/08/windows/eventlog'>
+      <SubjectUserName>bob &amp; co</SubjectUserName>
+      <Rules>
+        <Rule Id='1'>a</Rule>
+        <Rule Id='2'><Name><![CDATA[<b>]]></Name></Rule>
+      </Rules>
+      <Empty/>
+      <Note Lang='en'>hi</Note>
+    </LogFileCleared>
+  </UserData>
+</Event>"#;
+
+    fn strings(fields: &[(&str, &str)]) -> HashMap<String, SerdeValue> {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), SerdeValue::String(v.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn parses_user_data() {
+        let user_data = parse_user_data(USER_DATA).unwrap().unwrap();
+        assert_eq!(user_data.name, "UserData");
+
+        let payload = &user_data.children[0];
+        assert_eq!(payload.name, "LogFileCleared");
+        assert_eq!(
+            payload.attribute("xmlns"),
+            Some("http://manifests.microsoft.com/win/2004/08/windows/eventlog")
+        );
+        let names: Vec<&str> = payload.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["SubjectUserName", "Rules", "Empty", "Note"]);
+        assert_eq!(payload.children[0].text, "bob & co");
+        assert_eq!(payload.children[1].value(), "a<b>");
+        assert_eq!(payload.children[1].children[1].attribute("Id"), Some("2"));
+
+        let empty = "<Event><System/><UserData/></Event>";
+        assert_eq!(
+            parse_user_data(empty).unwrap(),
+            Some(Element {
+                name: "UserData".into(),
+                ..Default::default()
+            })
+        );
+
+        assert_eq!(parse_user_data("<Event><System/></Event>").unwrap(), None);
+        // UserData below Event only
+        let nested = "<Event><EventData><UserData><A>1</A></UserData></EventData></Event>";
+        assert_eq!(parse_user_data(nested).unwrap(), None);
+    }
+
+    #[test]
+    fn flattens_user_data() {
+        let user_data = parse_user_data(USER_DATA).unwrap().unwrap();
+
+        // repeated Rule keeps the last leaf, Rule 2 is not a leaf
+        assert_eq!(
+            user_data.flatten(UserDataFormat::Flat),
+            strings(&[
+                ("SubjectUserName", "bob & co"),
+                ("Rule", "a"),
+                ("Name", "<b>"),
+                ("Empty", ""),
+                ("Note", "hi"),
+            ])
+        );
+
+        assert_eq!(
+            user_data.flatten(UserDataFormat::Dotted),
+            strings(&[
+                ("SubjectUserName", "bob & co"),
+                ("Rules.Rule.0", "a"),
+                ("Rules.Rule.0.@Id", "1"),
+                ("Rules.Rule.1.@Id", "2"),
+                ("Rules.Rule.1.Name", "<b>"),
+                ("Empty", ""),
+                ("Note", "hi"),
+                ("Note.@Lang", "en"),
+            ])
+        );
+
+        let nested: HashMap<String, SerdeValue> = serde_json::from_value(serde_json::json!({
+            "SubjectUserName": "bob & co",
+            "Rules": {"Rule": [{"@Id": "1", "#text": "a"}, {"@Id": "2", "Name": "<b>"}]},
+            "Empty": "",
+            "Note": {"@Lang": "en", "#text": "hi"},
+        }))
+        .unwrap();
+        assert_eq!(user_data.flatten(UserDataFormat::Nested), nested);
+    }
+}
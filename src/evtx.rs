@@ -0,0 +1,298 @@
+mod binxml;
+
+use crate::binary::{read_u16, read_u32, read_u64};
+use crate::bookmark::Bookmark;
+use crate::error::{Error, ErrorKind, Result};
+use crate::event::{Output, WinLogEvent};
+use crate::source::{xml_position, EventSource};
+
+use binxml::Renderer;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const FILE_SIGNATURE: &[u8] = b"ElfFile\0";
+const CHUNK_SIGNATURE: &[u8] = b"ElfChnk\0";
+const RECORD_SIGNATURE: &[u8] = b"\x2a\x2a\x00\x00";
+
+const FILE_HEADER_SIZE: u64 = 4096;
+const CHUNK_SIZE: usize = 65536;
+const CHUNK_HEADER_SIZE: usize = 512;
+// signature, size, record id, written time
+const RECORD_HEADER_SIZE: usize = 24;
+
+#[derive(Debug, Clone)]
+pub struct FileHeader {
+    pub first_chunk_number: u64,
+    pub last_chunk_number: u64,
+    pub next_record_id: u64,
+    pub minor_version: u16,
+    pub major_version: u16,
+    pub chunk_count: u16,
+    pub flags: u32,
+}
+
+// Reader pulls events out of an exported .evtx file without any windows api,
+// events are returned in the same formats as the live reader.
+pub struct Reader<R> {
+    inner: R,
+    header: FileHeader,
+    output: Output,
+    chunk_count: u64,
+    next_chunk: u64,
+    chunk: Vec<u8>,
+    record_offset: usize,
+    free_space_offset: usize,
+    bookmark: Bookmark,
+}
+
+impl Reader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P, output: Output) -> Result<Self> {
+        let file = File::open(path).map_err(|e| Error::file("unable to open evtx file", e))?;
+        Reader::from_reader(BufReader::new(file), output)
+    }
+}
+
+impl<R: Read + Seek> Reader<R> {
+    pub fn from_reader(mut inner: R, output: Output) -> Result<Self> {
+        let mut buf = [0; 128];
+        inner
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| inner.read_exact(&mut buf))
+            .map_err(|e| Error::file("unable to read evtx file header", e))?;
+
+        if &buf[..8] != FILE_SIGNATURE {
+            return Err(Error {
+                kind: ErrorKind::File,
+                message: "invalid evtx file signature".into(),
+            });
+        }
+
+        let header = FileHeader {
+            first_chunk_number: read_u64(&buf, 8),
+            last_chunk_number: read_u64(&buf, 16),
+            next_record_id: read_u64(&buf, 24),
+            minor_version: read_u16(&buf, 36),
+            major_version: read_u16(&buf, 38),
+            chunk_count: read_u16(&buf, 42),
+            flags: read_u32(&buf, 120),
+        };
+
+        // header chunk count is not updated on dirty files, use file size instead
+        let len = inner
+            .seek(SeekFrom::End(0))
+            .map_err(|e| Error::file("unable to read evtx file size", e))?;
+        let chunk_count = len.saturating_sub(FILE_HEADER_SIZE) / CHUNK_SIZE as u64;
+
+        Ok(Self {
+            inner,
+            header,
+            output,
+            chunk_count,
+            next_chunk: 0,
+            chunk: Vec::new(),
+            record_offset: 0,
+            free_space_offset: 0,
+            bookmark: Bookmark::default(),
+        })
+    }
+
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    // returns rendered xml of next record, NoMoreLogs at end of file
+    fn next_xml(&mut self) -> Result<String> {
+        loop {
+            if self.record_offset + RECORD_HEADER_SIZE <= self.free_space_offset
+                && &self.chunk[self.record_offset..self.record_offset + 4] == RECORD_SIGNATURE
+            {
+                let xml = self.next_record()?;
+                if let Some((channel, record_id)) = xml_position(&xml) {
+                    self.bookmark.set(&channel, record_id);
+                }
+                return Ok(xml);
+            }
+
+            if !self.load_chunk()? {
+                return Err(Error {
+                    kind: ErrorKind::NoMoreLogs,
+                    message: "".to_owned(),
+                });
+            }
+        }
+    }
+
+    fn next_record(&mut self) -> Result<String> {
+        let offset = self.record_offset;
+        let size = read_u32(&self.chunk, offset + 4) as usize;
+
+        if size < RECORD_HEADER_SIZE + 4 || offset + size > self.free_space_offset {
+            // record size is corrupt, nothing after it can be trusted
+            self.record_offset = self.free_space_offset;
+            return Err(Error {
+                kind: ErrorKind::Event,
+                message: format!("evtx: invalid record size {} at offset {}", size, offset),
+            });
+        }
+
+        self.record_offset += size;
+
+        Renderer::new(&self.chunk)
+            .render(offset + RECORD_HEADER_SIZE, size - RECORD_HEADER_SIZE - 4)
+    }
+
+    // loads next chunk with valid signature, false when no chunks left
+    fn load_chunk(&mut self) -> Result<bool> {
+        while self.next_chunk < self.chunk_count {
+            let start = FILE_HEADER_SIZE + self.next_chunk * CHUNK_SIZE as u64;
+            self.next_chunk += 1;
+
+            self.chunk.resize(CHUNK_SIZE, 0);
+            self.inner
+                .seek(SeekFrom::Start(start))
+                .and_then(|_| self.inner.read_exact(&mut self.chunk))
+                .map_err(|e| Error::file("unable to read evtx chunk", e))?;
+
+            // unused chunks are zero filled
+            if &self.chunk[..8] != CHUNK_SIGNATURE {
+                continue;
+            }
+
+            self.record_offset = CHUNK_HEADER_SIZE;
+            self.free_space_offset = (read_u32(&self.chunk, 48) as usize).min(CHUNK_SIZE);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read + Seek> Iterator for Reader<R> {
+    type Item = Result<WinLogEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_xml() {
+            Ok(xml) => Some(WinLogEvent::from_xml(xml, self.output)),
+            Err(err) if err.kind == ErrorKind::NoMoreLogs => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+impl<R: Read + Seek> EventSource for Reader<R> {
+    fn next_event(&mut self) -> Result<WinLogEvent> {
+        let xml = self.next_xml()?;
+        WinLogEvent::from_xml(xml, self.output)
+    }
+
+    fn bookmark(&self) -> Result<Bookmark> {
+        Ok(self.bookmark.clone())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.next_chunk = self.chunk_count;
+        self.chunk = Vec::new();
+        self.record_offset = 0;
+        self.free_space_offset = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::RawEvent;
+    use std::io::Cursor;
+
+    // one chunk with Security 4624 records 1 to 3 of users alice, bob's and
+    // carol, system values and event data are template substitutions.
+    // Written by tests/fixtures/security_evtx.py.
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/security.evtx");
+
+    fn raw_events(reader: impl Iterator<Item = Result<WinLogEvent>>) -> Vec<Result<RawEvent>> {
+        reader
+            .map(|e| match e? {
+                WinLogEvent::Raw(e) => Ok(e),
+                e => panic!("unexpected output {:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_records() {
+        let reader = Reader::open(FIXTURE, Output::Raw).unwrap();
+        assert_eq!(reader.header().major_version, 3);
+
+        let events: Vec<RawEvent> = raw_events(reader)
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(events.len(), 3);
+
+        let e = &events[1];
+        assert_eq!(e.system.event_record_id, Some(2));
+        assert_eq!(e.system.event_id.id, 4624);
+        assert_eq!(e.system.channel, "Security");
+        assert_eq!(e.system.computer, "DC01 & <x>");
+        assert_eq!(e.system.keywords, "0x8020000000000000");
+        assert_eq!(e.system.security.user_id.as_deref(), Some("S-1-5-18"));
+        assert_eq!(
+            e.system.provider.name.as_deref(),
+            Some("Microsoft-Windows-Security-Auditing")
+        );
+        assert_eq!(
+            e.system.time_created.system_time.as_deref(),
+            Some("2019-04-17T18:40:02.1234567Z")
+        );
+
+        let data: Vec<(Option<&str>, Option<&str>)> = e
+            .event_data
+            .iter()
+            .flat_map(|d| d.data.iter().flatten())
+            .map(|d| (d.name.as_deref(), d.value.as_deref()))
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                (Some("TargetUserName"), Some("bob's")),
+                (Some("LogonType"), Some("10")),
+                (Some("Flags"), Some("a,b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn bookmark_follows_records() {
+        let mut reader = Reader::open(FIXTURE, Output::Xml).unwrap();
+
+        reader.next_event().unwrap();
+        reader.next_event().unwrap();
+        let mut expected = Bookmark::new();
+        expected.set("Security", 2);
+        assert_eq!(reader.bookmark().unwrap(), expected);
+    }
+
+    #[test]
+    fn invalid_record_size_skips_chunk() {
+        let mut file = std::fs::read(FIXTURE).unwrap();
+        // size of first record
+        let offset = FILE_HEADER_SIZE as usize + CHUNK_HEADER_SIZE + 4;
+        file[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let reader = Reader::from_reader(Cursor::new(file), Output::Raw).unwrap();
+        let events = raw_events(reader);
+
+        assert_eq!(events.len(), 1);
+        let err = events[0].as_ref().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Event);
+        assert!(err.message.contains("invalid record size"));
+    }
+
+    #[test]
+    fn invalid_signature() {
+        let err = Reader::from_reader(Cursor::new(vec![0; 4096]), Output::Raw)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::File);
+    }
+}
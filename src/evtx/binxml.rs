@@ -0,0 +1,1137 @@
+use crate::binary::{read_u16, read_u32, read_u64};
+use crate::error::{Error, ErrorKind, Result};
+
+use chrono::{TimeZone, Utc};
+use std::cell::Cell;
+
+// binxml tokens, 0x40 flag marks element with attributes / more data follows
+const TOKEN_END_OF_FRAGMENT: u8 = 0x00;
+const TOKEN_OPEN_START_ELEMENT: u8 = 0x01;
+const TOKEN_CLOSE_START_ELEMENT: u8 = 0x02;
+const TOKEN_CLOSE_EMPTY_ELEMENT: u8 = 0x03;
+const TOKEN_END_ELEMENT: u8 = 0x04;
+const TOKEN_VALUE: u8 = 0x05;
+const TOKEN_ATTRIBUTE: u8 = 0x06;
+const TOKEN_CDATA_SECTION: u8 = 0x07;
+const TOKEN_CHAR_REFERENCE: u8 = 0x08;
+const TOKEN_ENTITY_REFERENCE: u8 = 0x09;
+const TOKEN_PI_TARGET: u8 = 0x0a;
+const TOKEN_PI_DATA: u8 = 0x0b;
+const TOKEN_TEMPLATE_INSTANCE: u8 = 0x0c;
+const TOKEN_NORMAL_SUBSTITUTION: u8 = 0x0d;
+const TOKEN_OPTIONAL_SUBSTITUTION: u8 = 0x0e;
+const TOKEN_FRAGMENT_HEADER: u8 = 0x0f;
+
+const FLAG_MORE: u8 = 0x40;
+
+// value types
+const TYPE_NULL: u8 = 0x00;
+const TYPE_STRING: u8 = 0x01;
+const TYPE_ANSI_STRING: u8 = 0x02;
+const TYPE_INT8: u8 = 0x03;
+const TYPE_UINT8: u8 = 0x04;
+const TYPE_INT16: u8 = 0x05;
+const TYPE_UINT16: u8 = 0x06;
+const TYPE_INT32: u8 = 0x07;
+const TYPE_UINT32: u8 = 0x08;
+const TYPE_INT64: u8 = 0x09;
+const TYPE_UINT64: u8 = 0x0a;
+const TYPE_REAL32: u8 = 0x0b;
+const TYPE_REAL64: u8 = 0x0c;
+const TYPE_BOOL: u8 = 0x0d;
+const TYPE_BINARY: u8 = 0x0e;
+const TYPE_GUID: u8 = 0x0f;
+const TYPE_SIZE_T: u8 = 0x10;
+const TYPE_FILETIME: u8 = 0x11;
+const TYPE_SYSTIME: u8 = 0x12;
+const TYPE_SID: u8 = 0x13;
+const TYPE_HEX_INT32: u8 = 0x14;
+const TYPE_HEX_INT64: u8 = 0x15;
+const TYPE_BINXML: u8 = 0x21;
+const TYPE_ARRAY: u8 = 0x80;
+
+// template definition: next offset, guid, data size
+const TEMPLATE_HEADER_SIZE: usize = 24;
+
+// guard against templates referencing each other and deeply nested elements,
+// both recurse and files are untrusted
+const MAX_DEPTH: usize = 64;
+
+// work allowed for one record, a token costs one and text its length. Each
+// template instance renders its definition again, templates instancing the
+// next one twice would otherwise double the work on every level.
+const MAX_RENDER_COST: usize = 1 << 20;
+
+// seconds between 1601-01-01 and 1970-01-01
+const FILETIME_EPOCH_DIFF: i64 = 11_644_473_600;
+
+#[derive(Clone, Copy)]
+struct Value {
+    value_type: u8,
+    offset: usize,
+    size: usize,
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8], pos: usize, end: usize) -> Self {
+        Self {
+            data,
+            pos,
+            end: end.min(data.len()),
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.end {
+            return Err(parse_error("unexpected end of binxml data"));
+        }
+        let b = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(b)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        if self.pos >= self.end {
+            return Err(parse_error("unexpected end of binxml data"));
+        }
+        Ok(self.data[self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(read_u16(self.bytes(2)?, 0))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(read_u32(self.bytes(4)?, 0))
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.bytes(len).map(|_| ())
+    }
+}
+
+// Renderer converts binxml inside a single chunk into event xml, all offsets
+// (names, templates, substitution values) are relative to the chunk start.
+pub(crate) struct Renderer<'a> {
+    chunk: &'a [u8],
+    // fragments embedded in substitution values omit element dependency id
+    in_substitution: Cell<bool>,
+    // render cost left for the current record
+    budget: Cell<usize>,
+}
+
+impl<'a> Renderer<'a> {
+    pub(crate) fn new(chunk: &'a [u8]) -> Self {
+        Self {
+            chunk,
+            in_substitution: Cell::new(false),
+            budget: Cell::new(MAX_RENDER_COST),
+        }
+    }
+
+    pub(crate) fn render(&self, offset: usize, size: usize) -> Result<String> {
+        let mut out = String::new();
+        let mut cur = Cursor::new(self.chunk, offset, offset + size);
+        self.budget.set(MAX_RENDER_COST);
+        self.in_substitution.set(false);
+        self.content(&mut cur, &[], &mut out, false, 0)?;
+        Ok(out)
+    }
+
+    fn spend(&self, cost: usize) -> Result<()> {
+        match self.budget.get().checked_sub(cost) {
+            Some(left) => {
+                self.budget.set(left);
+                Ok(())
+            }
+            None => Err(parse_error("binxml record too large to render")),
+        }
+    }
+
+    // renders fragment with substitution flag set, previous one is restored
+    fn fragment(
+        &self,
+        cur: &mut Cursor,
+        values: &[Value],
+        out: &mut String,
+        in_substitution: bool,
+        depth: usize,
+    ) -> Result<()> {
+        let previous = self.in_substitution.replace(in_substitution);
+        let result = self.content(cur, values, out, false, depth);
+        self.in_substitution.set(previous);
+        result
+    }
+
+    fn content(
+        &self,
+        cur: &mut Cursor,
+        values: &[Value],
+        out: &mut String,
+        in_element: bool,
+        depth: usize,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(parse_error("binxml nesting too deep"));
+        }
+
+        loop {
+            // fragment can end without explicit token
+            if !in_element && cur.pos >= cur.end {
+                return Ok(());
+            }
+
+            let token = cur.u8()?;
+            self.spend(1)?;
+            match token & !FLAG_MORE {
+                TOKEN_END_OF_FRAGMENT => return Ok(()),
+                TOKEN_END_ELEMENT if in_element => return Ok(()),
+                TOKEN_FRAGMENT_HEADER => cur.skip(3)?,
+                TOKEN_OPEN_START_ELEMENT => self.element(cur, token, values, out, depth)?,
+                TOKEN_TEMPLATE_INSTANCE => self.template(cur, out, depth)?,
+                TOKEN_VALUE
+                | TOKEN_CHAR_REFERENCE
+                | TOKEN_ENTITY_REFERENCE
+                | TOKEN_NORMAL_SUBSTITUTION
+                | TOKEN_OPTIONAL_SUBSTITUTION => {
+                    self.text(cur, token, values, out, depth)?;
+                }
+                TOKEN_CDATA_SECTION => {
+                    // section can't hold its terminator, split it there
+                    let s = utf16_string(cur)?.replace("]]>", "]]]]><![CDATA[>");
+                    self.spend(s.len())?;
+                    out.push_str(&format!("<![CDATA[{}]]>", s));
+                }
+                TOKEN_PI_TARGET => {
+                    let name = self.name(cur)?;
+                    out.push_str(&format!("<?{}", name));
+                    if cur.peek()? == TOKEN_PI_DATA {
+                        cur.skip(1)?;
+                        out.push_str(&format!(" {}", utf16_string(cur)?));
+                    }
+                    out.push_str("?>");
+                }
+                _ => {
+                    return Err(parse_error(&format!(
+                        "unexpected binxml token 0x{:02x} at offset {}",
+                        token,
+                        cur.pos - 1
+                    )))
+                }
+            }
+        }
+    }
+
+    // writes text like token, returns true when optional substitution was empty
+    fn text(
+        &self,
+        cur: &mut Cursor,
+        token: u8,
+        values: &[Value],
+        out: &mut String,
+        depth: usize,
+    ) -> Result<bool> {
+        match token & !FLAG_MORE {
+            TOKEN_VALUE => {
+                // value type is always string for value tokens
+                cur.skip(1)?;
+                let text = escape(&utf16_string(cur)?);
+                self.spend(text.len())?;
+                out.push_str(&text);
+                Ok(false)
+            }
+            TOKEN_CHAR_REFERENCE => {
+                out.push_str(&format!("&#{};", cur.u16()?));
+                Ok(false)
+            }
+            TOKEN_ENTITY_REFERENCE => {
+                out.push_str(&format!("&{};", self.name(cur)?));
+                Ok(false)
+            }
+            _ => {
+                let id = cur.u16()? as usize;
+                let _value_type = cur.u8()?;
+
+                let value = match values.get(id) {
+                    Some(v) => *v,
+                    None => Value {
+                        value_type: TYPE_NULL,
+                        offset: 0,
+                        size: 0,
+                    },
+                };
+
+                if value.value_type == TYPE_NULL || value.size == 0 {
+                    return Ok(token == TOKEN_OPTIONAL_SUBSTITUTION);
+                }
+
+                if value.value_type == TYPE_BINXML {
+                    let mut nested =
+                        Cursor::new(self.chunk, value.offset, value.offset + value.size);
+                    self.fragment(&mut nested, &[], out, true, depth + 1)?;
+                } else {
+                    let data = &self.chunk[value.offset..value.offset + value.size];
+                    let text = escape(&format_value(value.value_type, data)?);
+                    self.spend(text.len())?;
+                    out.push_str(&text);
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn element(
+        &self,
+        cur: &mut Cursor,
+        token: u8,
+        values: &[Value],
+        out: &mut String,
+        depth: usize,
+    ) -> Result<()> {
+        let start = cur.pos - 1;
+        let has_attributes = token & FLAG_MORE != 0;
+
+        // dependency identifier is not always written where expected, fall
+        // back to the other layout when it gives the only valid name reference
+        let with_dependency = if self.in_substitution.get() {
+            !self.valid_name_ref(start + 5) && self.valid_name_ref(start + 7)
+        } else {
+            self.valid_name_ref(start + 7) || !self.valid_name_ref(start + 5)
+        };
+        cur.skip(if with_dependency { 6 } else { 4 })?;
+
+        let name = self.name(cur)?;
+        if has_attributes {
+            let _attribute_list_size = cur.u32()?;
+        }
+
+        out.push('<');
+        out.push_str(&name);
+
+        if has_attributes {
+            loop {
+                let token = cur.peek()?;
+                if token & !FLAG_MORE != TOKEN_ATTRIBUTE {
+                    break;
+                }
+                cur.skip(1)?;
+
+                let attr_name = self.name(cur)?;
+                let mut value = String::new();
+                let mut omitted = false;
+
+                loop {
+                    let t = cur.u8()?;
+                    omitted |= self.text(cur, t, values, &mut value, depth)?;
+                    if t & FLAG_MORE == 0 {
+                        break;
+                    }
+                }
+
+                if !(omitted && value.is_empty()) {
+                    out.push_str(&format!(" {}='{}'", attr_name, value));
+                }
+
+                if token & FLAG_MORE == 0 {
+                    break;
+                }
+            }
+        }
+
+        match cur.u8()? {
+            TOKEN_CLOSE_EMPTY_ELEMENT => out.push_str("/>"),
+            TOKEN_CLOSE_START_ELEMENT => {
+                out.push('>');
+                self.content(cur, values, out, true, depth + 1)?;
+                out.push_str(&format!("</{}>", name));
+            }
+            t => {
+                return Err(parse_error(&format!(
+                    "unexpected binxml token 0x{:02x} in element {}",
+                    t, name
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn template(&self, cur: &mut Cursor, out: &mut String, depth: usize) -> Result<()> {
+        let _unknown = cur.u8()?;
+        let _template_id = cur.u32()?;
+        let definition = cur.u32()? as usize;
+
+        // template definition written inline on first use in chunk
+        if definition == cur.pos {
+            cur.skip(20)?;
+            let size = cur.u32()? as usize;
+            cur.skip(size)?;
+        }
+
+        let count = cur.u32()? as usize;
+        let mut sizes = Vec::new();
+        for _ in 0..count {
+            let size = cur.u16()? as usize;
+            let value_type = cur.u8()?;
+            let _padding = cur.u8()?;
+            sizes.push((size, value_type));
+        }
+
+        let mut values = Vec::new();
+        for (size, value_type) in sizes {
+            values.push(Value {
+                value_type,
+                offset: cur.pos,
+                size,
+            });
+            cur.skip(size)?;
+        }
+
+        if definition + TEMPLATE_HEADER_SIZE > self.chunk.len() {
+            return Err(parse_error("template definition out of chunk bounds"));
+        }
+        let size = read_u32(self.chunk, definition + 20) as usize;
+        let start = definition + TEMPLATE_HEADER_SIZE;
+
+        let mut body = Cursor::new(self.chunk, start, start + size);
+        self.fragment(&mut body, &values, out, false, depth + 1)
+    }
+
+    // name reference, name is stored inline when offset points to itself
+    fn name(&self, cur: &mut Cursor) -> Result<String> {
+        let offset = cur.u32()? as usize;
+        let (name, size) = self.name_at(offset)?;
+        if offset == cur.pos {
+            cur.skip(size)?;
+        }
+        Ok(name)
+    }
+
+    fn name_at(&self, offset: usize) -> Result<(String, usize)> {
+        if offset + 8 > self.chunk.len() {
+            return Err(parse_error("name offset out of chunk bounds"));
+        }
+        let len = read_u16(self.chunk, offset + 6) as usize;
+        let start = offset + 8;
+        if start + len * 2 > self.chunk.len() {
+            return Err(parse_error("name out of chunk bounds"));
+        }
+        let name = decode_utf16(&self.chunk[start..start + len * 2]);
+        // next offset, hash, count, chars, null terminator
+        Ok((name, 8 + len * 2 + 2))
+    }
+
+    fn valid_name_ref(&self, pos: usize) -> bool {
+        if pos + 4 > self.chunk.len() {
+            return false;
+        }
+        let offset = read_u32(self.chunk, pos) as usize;
+        if offset != pos + 4 && offset >= pos {
+            return false;
+        }
+        match self.name_at(offset) {
+            Ok((name, _)) => !name.is_empty() && !name.contains('\u{0}'),
+            Err(_) => false,
+        }
+    }
+}
+
+fn format_value(value_type: u8, data: &[u8]) -> Result<String> {
+    if value_type & TYPE_ARRAY != 0 {
+        return format_array(value_type & !TYPE_ARRAY, data);
+    }
+
+    let value = match value_type {
+        TYPE_NULL => String::new(),
+        TYPE_STRING => decode_utf16(data).trim_end_matches('\u{0}').to_string(),
+        TYPE_ANSI_STRING => String::from_utf8_lossy(data)
+            .trim_end_matches('\u{0}')
+            .to_string(),
+        TYPE_INT8 => (fixed(data, 1)?[0] as i8).to_string(),
+        TYPE_UINT8 => fixed(data, 1)?[0].to_string(),
+        TYPE_INT16 => (read_u16(fixed(data, 2)?, 0) as i16).to_string(),
+        TYPE_UINT16 => read_u16(fixed(data, 2)?, 0).to_string(),
+        TYPE_INT32 => (read_u32(fixed(data, 4)?, 0) as i32).to_string(),
+        TYPE_UINT32 => read_u32(fixed(data, 4)?, 0).to_string(),
+        TYPE_INT64 => (read_u64(fixed(data, 8)?, 0) as i64).to_string(),
+        TYPE_UINT64 => read_u64(fixed(data, 8)?, 0).to_string(),
+        TYPE_REAL32 => f32::from_bits(read_u32(fixed(data, 4)?, 0)).to_string(),
+        TYPE_REAL64 => f64::from_bits(read_u64(fixed(data, 8)?, 0)).to_string(),
+        TYPE_BOOL => (read_u32(fixed(data, 4)?, 0) != 0).to_string(),
+        TYPE_BINARY => data.iter().map(|b| format!("{:02X}", b)).collect(),
+        TYPE_GUID => format_guid(fixed(data, 16)?),
+        TYPE_SIZE_T if data.len() == 4 => format!("0x{:08x}", read_u32(data, 0)),
+        TYPE_SIZE_T => format!("0x{:016x}", read_u64(fixed(data, 8)?, 0)),
+        TYPE_FILETIME => format_filetime(read_u64(fixed(data, 8)?, 0)),
+        TYPE_SYSTIME => format_systime(fixed(data, 16)?),
+        TYPE_SID => format_sid(data)?,
+        TYPE_HEX_INT32 => format!("0x{:x}", read_u32(fixed(data, 4)?, 0)),
+        TYPE_HEX_INT64 => format!("0x{:x}", read_u64(fixed(data, 8)?, 0)),
+        _ => {
+            return Err(parse_error(&format!(
+                "unsupported binxml value type 0x{:02x}",
+                value_type
+            )))
+        }
+    };
+    Ok(value)
+}
+
+fn format_array(value_type: u8, data: &[u8]) -> Result<String> {
+    let items: Vec<String> = match value_type {
+        TYPE_STRING => decode_utf16(data)
+            .trim_end_matches('\u{0}')
+            .split('\u{0}')
+            .map(|s| s.to_string())
+            .collect(),
+        TYPE_ANSI_STRING => String::from_utf8_lossy(data)
+            .trim_end_matches('\u{0}')
+            .split('\u{0}')
+            .map(|s| s.to_string())
+            .collect(),
+        _ => {
+            let size = match value_type {
+                TYPE_INT8 | TYPE_UINT8 => 1,
+                TYPE_INT16 | TYPE_UINT16 => 2,
+                TYPE_INT32 | TYPE_UINT32 | TYPE_REAL32 | TYPE_BOOL | TYPE_HEX_INT32 => 4,
+                TYPE_INT64 | TYPE_UINT64 | TYPE_REAL64 | TYPE_FILETIME | TYPE_HEX_INT64 => 8,
+                TYPE_GUID | TYPE_SYSTIME => 16,
+                _ => {
+                    return Err(parse_error(&format!(
+                        "unsupported binxml array type 0x{:02x}",
+                        value_type
+                    )))
+                }
+            };
+            data.chunks(size)
+                .map(|c| format_value(value_type, c))
+                .collect::<Result<Vec<String>>>()?
+        }
+    };
+    Ok(items.join(","))
+}
+
+// {XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}, first three groups little endian
+fn format_guid(b: &[u8]) -> String {
+    format!(
+        "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+        read_u32(b, 0),
+        read_u16(b, 4),
+        read_u16(b, 6),
+        b[8],
+        b[9],
+        b[10],
+        b[11],
+        b[12],
+        b[13],
+        b[14],
+        b[15]
+    )
+}
+
+// S-<revision>-<authority>-<sub authorities>
+fn format_sid(b: &[u8]) -> Result<String> {
+    if b.len() < 8 {
+        return Err(parse_error("invalid sid value"));
+    }
+    let count = b[1] as usize;
+    if b.len() < 8 + count * 4 {
+        return Err(parse_error("invalid sid value"));
+    }
+
+    let authority = b[2..8]
+        .iter()
+        .fold(0u64, |acc, x| (acc << 8) | u64::from(*x));
+
+    let mut sid = format!("S-{}-{}", b[0], authority);
+    for i in 0..count {
+        sid.push_str(&format!("-{}", read_u32(b, 8 + i * 4)));
+    }
+    Ok(sid)
+}
+
+pub(crate) fn format_filetime(ticks: u64) -> String {
+    let secs = (ticks / 10_000_000) as i64 - FILETIME_EPOCH_DIFF;
+    let fraction = ticks % 10_000_000;
+    match Utc.timestamp_opt(secs, 0).single() {
+        Some(t) => format!("{}.{:07}Z", t.format("%Y-%m-%dT%H:%M:%S"), fraction),
+        None => String::new(),
+    }
+}
+
+// SYSTEMTIME: year, month, day of week, day, hour, minute, second, milliseconds
+fn format_systime(b: &[u8]) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        read_u16(b, 0),
+        read_u16(b, 2),
+        read_u16(b, 6),
+        read_u16(b, 8),
+        read_u16(b, 10),
+        read_u16(b, 12),
+        read_u16(b, 14)
+    )
+}
+
+fn utf16_string(cur: &mut Cursor) -> Result<String> {
+    let len = cur.u16()? as usize;
+    Ok(decode_utf16(cur.bytes(len * 2)?))
+}
+
+fn decode_utf16(b: &[u8]) -> String {
+    let chars: Vec<u16> = b
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn fixed(data: &[u8], size: usize) -> Result<&[u8]> {
+    if data.len() < size {
+        return Err(parse_error("binxml value too short"));
+    }
+    Ok(&data[..size])
+}
+
+fn parse_error(message: &str) -> Error {
+    Error {
+        kind: ErrorKind::Event,
+        message: format!("evtx: {}", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // writes binxml into a chunk, names are inlined on first use
+    #[derive(Default)]
+    struct Builder {
+        buf: Vec<u8>,
+        names: HashMap<String, u32>,
+        // fragment of binxml substitution, elements have no dependency id
+        substitution: bool,
+    }
+
+    impl Builder {
+        fn name(&mut self, name: &str) -> &mut Self {
+            if let Some(offset) = self.names.get(name) {
+                let offset = *offset;
+                return self.u32(offset);
+            }
+
+            let offset = self.buf.len() as u32 + 4;
+            self.names.insert(name.to_owned(), offset);
+            self.u32(offset).u32(0).u16(0).u16(name.len() as u16);
+            self.utf16(name).u16(0)
+        }
+
+        fn header(&mut self) -> &mut Self {
+            self.bytes(&[TOKEN_FRAGMENT_HEADER, 1, 1, 0])
+        }
+
+        fn open(&mut self, name: &str, attributes: bool) -> &mut Self {
+            let flag = if attributes { FLAG_MORE } else { 0 };
+            self.bytes(&[TOKEN_OPEN_START_ELEMENT | flag]);
+            if !self.substitution {
+                self.u16(0xffff);
+            }
+            self.u32(0).name(name);
+            if attributes {
+                self.u32(0);
+            }
+            self
+        }
+
+        fn attribute(&mut self, name: &str, more: bool) -> &mut Self {
+            let flag = if more { FLAG_MORE } else { 0 };
+            self.bytes(&[TOKEN_ATTRIBUTE | flag]).name(name)
+        }
+
+        fn close_start(&mut self) -> &mut Self {
+            self.bytes(&[TOKEN_CLOSE_START_ELEMENT])
+        }
+
+        fn close_empty(&mut self) -> &mut Self {
+            self.bytes(&[TOKEN_CLOSE_EMPTY_ELEMENT])
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.bytes(&[TOKEN_END_ELEMENT])
+        }
+
+        fn eof(&mut self) -> &mut Self {
+            self.bytes(&[TOKEN_END_OF_FRAGMENT])
+        }
+
+        fn text(&mut self, text: &str) -> &mut Self {
+            self.bytes(&[TOKEN_VALUE, TYPE_STRING])
+                .u16(text.encode_utf16().count() as u16)
+                .utf16(text)
+        }
+
+        fn substitution(&mut self, id: u16, value_type: u8, optional: bool) -> &mut Self {
+            let token = if optional {
+                TOKEN_OPTIONAL_SUBSTITUTION
+            } else {
+                TOKEN_NORMAL_SUBSTITUTION
+            };
+            self.bytes(&[token]).u16(id).bytes(&[value_type])
+        }
+
+        // template instance, definition is written inline when offset is None
+        // and its offset returned for later instances
+        fn template(
+            &mut self,
+            definition: Option<u32>,
+            body: impl FnOnce(&mut Builder),
+            values: &[(u8, Vec<u8>)],
+        ) -> u32 {
+            self.bytes(&[TOKEN_TEMPLATE_INSTANCE, 1]).u32(0x1234);
+
+            let offset = match definition {
+                Some(offset) => {
+                    self.u32(offset);
+                    offset
+                }
+                None => {
+                    let offset = self.buf.len() as u32 + 4;
+                    self.u32(offset).u32(0).bytes(&[0; 16]);
+
+                    let size_at = self.buf.len();
+                    self.u32(0);
+                    body(self);
+                    let size = (self.buf.len() - size_at - 4) as u32;
+                    self.buf[size_at..size_at + 4].copy_from_slice(&size.to_le_bytes());
+                    offset
+                }
+            };
+
+            self.u32(values.len() as u32);
+            for (value_type, value) in values {
+                self.u16(value.len() as u16).bytes(&[*value_type, 0]);
+            }
+            for (_, value) in values {
+                self.bytes(value);
+            }
+            offset
+        }
+
+        fn bytes(&mut self, b: &[u8]) -> &mut Self {
+            self.buf.extend_from_slice(b);
+            self
+        }
+
+        fn u16(&mut self, v: u16) -> &mut Self {
+            self.bytes(&v.to_le_bytes())
+        }
+
+        fn u32(&mut self, v: u32) -> &mut Self {
+            self.bytes(&v.to_le_bytes())
+        }
+
+        fn utf16(&mut self, s: &str) -> &mut Self {
+            for c in s.encode_utf16() {
+                self.u16(c);
+            }
+            self
+        }
+
+        fn render(&self) -> Result<String> {
+            Renderer::new(&self.buf).render(0, self.buf.len())
+        }
+    }
+
+    fn utf16z(s: &str) -> Vec<u8> {
+        let mut b: Vec<u8> = s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+        b.extend_from_slice(&[0, 0]);
+        b
+    }
+
+    // template with one Data element per value
+    fn render_values(values: &[(u8, Vec<u8>)]) -> Result<String> {
+        let types: Vec<u8> = values.iter().map(|(t, _)| *t).collect();
+
+        let mut b = Builder::default();
+        b.header();
+        b.template(
+            None,
+            |b| {
+                b.header().open("EventData", false).close_start();
+                for (i, t) in types.iter().enumerate() {
+                    b.open("Data", false)
+                        .close_start()
+                        .substitution(i as u16, *t, false)
+                        .end();
+                }
+                b.end().eof();
+            },
+            values,
+        );
+        b.eof();
+        b.render()
+    }
+
+    fn data(values: &[&str]) -> String {
+        let data: String = values
+            .iter()
+            .map(|v| format!("<Data>{}</Data>", v))
+            .collect();
+        format!("<EventData>{}</EventData>", data)
+    }
+
+    #[test]
+    fn inline_names_attributes_and_text() {
+        let mut b = Builder::default();
+        b.header()
+            .open("Event", true)
+            .attribute("Name", true)
+            .text("a&b")
+            .attribute("Id", false)
+            .text("1")
+            .close_start()
+            .open("Data", false)
+            .close_empty()
+            // second use refers to the inlined name
+            .open("Data", false)
+            .close_start()
+            .text("<x>")
+            .end()
+            .end()
+            .eof();
+
+        assert_eq!(
+            b.render().unwrap(),
+            "<Event Name='a&amp;b' Id='1'><Data/><Data>&lt;x&gt;</Data></Event>"
+        );
+    }
+
+    #[test]
+    fn substitution_value_types() {
+        let filetime = 132_000_000_000_000_000u64 + 1_234_567;
+        let mut systime = Vec::new();
+        for v in &[2020u16, 1, 4, 2, 3, 4, 5, 6] {
+            systime.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut sid = vec![1, 1, 0, 0, 0, 0, 0, 5];
+        sid.extend_from_slice(&18u32.to_le_bytes());
+        let guid = [
+            0x25, 0x96, 0x84, 0x54, 0x78, 0x54, 0x94, 0x49, 0xa5, 0xba, 0x3e, 0x3b, 0x03, 0x28,
+            0xc3, 0x0d,
+        ];
+
+        let values: Vec<(u8, Vec<u8>)> = vec![
+            (TYPE_STRING, utf16z("héllo <&>")),
+            (TYPE_ANSI_STRING, b"abc\0".to_vec()),
+            (TYPE_INT8, vec![0xfb]),
+            (TYPE_UINT8, vec![200]),
+            (TYPE_INT16, (-300i16).to_le_bytes().to_vec()),
+            (TYPE_UINT16, 60000u16.to_le_bytes().to_vec()),
+            (TYPE_INT32, (-70000i32).to_le_bytes().to_vec()),
+            (TYPE_UINT32, 4_000_000_000u32.to_le_bytes().to_vec()),
+            (TYPE_INT64, (-5_000_000_000i64).to_le_bytes().to_vec()),
+            (TYPE_UINT64, u64::MAX.to_le_bytes().to_vec()),
+            (TYPE_REAL32, 1.5f32.to_le_bytes().to_vec()),
+            (TYPE_REAL64, (-2.25f64).to_le_bytes().to_vec()),
+            (TYPE_BOOL, 1u32.to_le_bytes().to_vec()),
+            (TYPE_BINARY, vec![0x0a, 0xbc]),
+            (TYPE_GUID, guid.to_vec()),
+            (TYPE_SIZE_T, 42u32.to_le_bytes().to_vec()),
+            (TYPE_FILETIME, filetime.to_le_bytes().to_vec()),
+            (TYPE_SYSTIME, systime),
+            (TYPE_SID, sid),
+            (TYPE_HEX_INT32, 31u32.to_le_bytes().to_vec()),
+            (
+                TYPE_HEX_INT64,
+                0x8020_0000_0000_0000u64.to_le_bytes().to_vec(),
+            ),
+            (
+                TYPE_ARRAY | TYPE_STRING,
+                [utf16z("a"), utf16z("b")].concat(),
+            ),
+            (TYPE_ARRAY | TYPE_UINT16, vec![1, 0, 2, 0]),
+        ];
+
+        assert_eq!(
+            render_values(&values).unwrap(),
+            data(&[
+                "héllo &lt;&amp;&gt;",
+                "abc",
+                "-5",
+                "200",
+                "-300",
+                "60000",
+                "-70000",
+                "4000000000",
+                "-5000000000",
+                "18446744073709551615",
+                "1.5",
+                "-2.25",
+                "true",
+                "0ABC",
+                "{54849625-5478-4994-A5BA-3E3B0328C30D}",
+                "0x0000002a",
+                "2019-04-17T18:40:00.1234567Z",
+                "2020-01-02T03:04:05.006Z",
+                "S-1-5-18",
+                "0x1f",
+                "0x8020000000000000",
+                "a,b",
+                "1,2",
+            ])
+        );
+    }
+
+    #[test]
+    fn optional_substitution_omits_attribute() {
+        let mut b = Builder::default();
+        b.header();
+        b.template(
+            None,
+            |b| {
+                b.header()
+                    .open("Provider", true)
+                    .attribute("Name", true)
+                    .substitution(0, TYPE_STRING, true)
+                    .attribute("Guid", false)
+                    .substitution(1, TYPE_GUID, true)
+                    .close_empty()
+                    .eof();
+            },
+            &[(TYPE_STRING, utf16z("p")), (TYPE_NULL, Vec::new())],
+        );
+        b.eof();
+
+        assert_eq!(b.render().unwrap(), "<Provider Name='p'/>");
+    }
+
+    #[test]
+    fn template_reused_and_binxml_substitution() {
+        let mut b = Builder::default();
+        b.header();
+        let definition = b.template(
+            None,
+            |b| {
+                b.header()
+                    .open("Event", false)
+                    .close_start()
+                    .substitution(0, TYPE_BINXML, true)
+                    .end()
+                    .eof();
+            },
+            &[(TYPE_NULL, Vec::new())],
+        );
+
+        // nested fragment refers to names written before it
+        let mut nested = Builder {
+            names: b.names.clone(),
+            substitution: true,
+            ..Default::default()
+        };
+        nested.header().open("Event", false).close_empty().eof();
+        let fragment = nested.buf.clone();
+
+        b.template(Some(definition), |_| (), &[(TYPE_BINXML, fragment)]);
+        b.eof();
+
+        assert_eq!(
+            b.render().unwrap(),
+            "<Event></Event><Event><Event/></Event>"
+        );
+    }
+
+    #[test]
+    fn cdata_terminator_is_split() {
+        let text = "a]]>b";
+        let mut b = Builder::default();
+        b.header()
+            .open("Data", false)
+            .close_start()
+            .bytes(&[TOKEN_CDATA_SECTION])
+            .u16(text.len() as u16)
+            .utf16(text)
+            .end()
+            .eof();
+
+        let xml = b.render().unwrap();
+        assert_eq!(xml, "<Data><![CDATA[a]]]]><![CDATA[>b]]></Data>");
+
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let mut buf = Vec::new();
+        let mut content = String::new();
+        loop {
+            match reader.read_event(&mut buf).unwrap() {
+                quick_xml::events::Event::CData(c) => {
+                    content.push_str(std::str::from_utf8(&c.unescaped().unwrap()).unwrap())
+                }
+                quick_xml::events::Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+        assert_eq!(content, text);
+    }
+
+    // elements are read with and without dependency id in template
+    // definitions and in binxml substitution values
+    #[test]
+    fn dependency_id_in_either_layout() {
+        for (body_substitution, fragment_substitution) in
+            &[(false, true), (true, true), (false, false), (true, false)]
+        {
+            let mut b = Builder {
+                substitution: *body_substitution,
+                ..Default::default()
+            };
+            b.header();
+            let definition = b.template(
+                None,
+                |b| {
+                    b.header()
+                        .open("Event", true)
+                        .attribute("Id", false)
+                        .text("1")
+                        .close_start()
+                        .substitution(0, TYPE_BINXML, true)
+                        .end()
+                        .eof();
+                },
+                &[(TYPE_NULL, Vec::new())],
+            );
+
+            let mut nested = Builder {
+                names: b.names.clone(),
+                substitution: *fragment_substitution,
+                ..Default::default()
+            };
+            // names are referenced by chunk offset, fragment follows the
+            // instance header, value count and its one descriptor
+            nested.buf = b.buf.clone();
+            nested.bytes(&[0; 18]);
+            let fragment_start = nested.buf.len();
+            nested
+                .header()
+                .open("Data", false)
+                .close_start()
+                .text("x")
+                .end()
+                .open("Event", false)
+                .close_empty()
+                .eof();
+            let fragment = nested.buf[fragment_start..].to_vec();
+
+            b.template(Some(definition), |_| (), &[(TYPE_BINXML, fragment)]);
+            b.eof();
+
+            assert_eq!(
+                b.render().unwrap(),
+                "<Event Id='1'></Event><Event Id='1'><Data>x</Data><Event/></Event>",
+                "{} {}",
+                body_substitution,
+                fragment_substitution
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_and_invalid_records() {
+        let mut b = Builder::default();
+        b.header()
+            .open("Event", false)
+            .close_start()
+            .text("abc")
+            .end()
+            .eof();
+
+        let truncated = Renderer::new(&b.buf).render(0, b.buf.len() - 4);
+        assert!(truncated.is_err());
+
+        let mut invalid = Builder::default();
+        invalid.header().bytes(&[0x1f]);
+        let err = invalid.render().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Event);
+        assert!(err.message.contains("unexpected binxml token 0x1f"));
+
+        let unsupported = render_values(&[(0x20, vec![1])]).unwrap_err();
+        assert!(unsupported
+            .message
+            .contains("unsupported binxml value type"));
+
+        let short = render_values(&[(TYPE_UINT32, vec![1, 2])]).unwrap_err();
+        assert!(short.message.contains("too short"));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let mut b = Builder::default();
+        b.header();
+        for _ in 0..10_000 {
+            b.open("E", false).close_start();
+        }
+
+        let err = b.render().unwrap_err();
+        assert!(err.message.contains("nesting too deep"));
+    }
+
+    // each template instances the next one twice, last one writes leaf
+    fn template_chain(b: &mut Builder, level: usize, levels: usize, leaf: &str) {
+        if level == levels {
+            b.header()
+                .open("E", false)
+                .close_start()
+                .text(leaf)
+                .end()
+                .eof();
+            return;
+        }
+        b.header();
+        let definition = b.template(None, |b| template_chain(b, level + 1, levels, leaf), &[]);
+        b.template(Some(definition), |_| (), &[]);
+        b.eof();
+    }
+
+    #[test]
+    fn render_cost_is_limited() {
+        let mut b = Builder::default();
+        template_chain(&mut b, 0, 3, "x");
+        assert_eq!(b.render().unwrap(), "<E>x</E>".repeat(8));
+
+        let mut b = Builder::default();
+        template_chain(&mut b, 0, 40, "");
+        let err = b.render().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Event);
+        assert!(err.message.contains("too large to render"));
+
+        // few tokens, long text repeated by the chain
+        let mut b = Builder::default();
+        template_chain(&mut b, 0, 12, &"x".repeat(1000));
+        assert!(b.buf.len() < 4096);
+        let err = b.render().unwrap_err();
+        assert!(err.message.contains("too large to render"));
+    }
+}
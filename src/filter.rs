@@ -1,5 +1,17 @@
+use crate::error::{Error, ErrorKind, Result};
+use crate::event::{Event, RawEvent};
+use crate::query::{self, compare_text, Action, Expr, Operator, QueryList, Selector};
+
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
 use core::convert::Into;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// windows rejects expressions with more EventID comparisons
+const MAX_EVENT_ID_EXPRESSIONS: usize = 22;
 
 #[derive(Deserialize, Default, Serialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
@@ -8,7 +20,196 @@ pub struct Config {
     pub level: Option<Vec<Level>>,
     pub event_id: Option<String>,
     pub ignore_older: Option<u64>, // secs
+    // events created in the time window, both ends included
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub provider: Option<Vec<String>>,
+    pub event_data: Option<Vec<DataFilter>>,
+    // events with any of the keywords
+    pub keywords: Option<Vec<Keyword>>,
+    pub exclude: Option<Exclude>,
+}
+
+// Exclude removes events matching any of the exclusions from the events
+// selected by config, each exclusion is a Suppress in generated query
+#[derive(Deserialize, Default, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Exclude {
     pub provider: Option<Vec<String>>,
+    pub level: Option<Vec<Level>>,
+    // user SIDs, S-1-5-18
+    pub user: Option<Vec<String>>,
+    // events matching all data filters are excluded
+    pub event_data: Option<Vec<DataFilter>>,
+}
+
+// Keyword is a well known windows event keyword or a raw keywords mask,
+// config files give it like FromStr, as name or mask
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Keyword {
+    AuditSuccess,
+    AuditFailure,
+    Classic,
+    EventLogClassic,
+    CorrelationHint,
+    ResponseTime,
+    WdiContext,
+    WdiDiag,
+    Sqm,
+    Mask(u64),
+}
+
+// keyword names, rendered names have spaces
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("AuditSuccess", Keyword::AuditSuccess),
+    ("AuditFailure", Keyword::AuditFailure),
+    ("Classic", Keyword::Classic),
+    ("EventLogClassic", Keyword::EventLogClassic),
+    ("CorrelationHint", Keyword::CorrelationHint),
+    ("ResponseTime", Keyword::ResponseTime),
+    ("WdiContext", Keyword::WdiContext),
+    ("WdiDiag", Keyword::WdiDiag),
+    ("Sqm", Keyword::Sqm),
+];
+
+impl Keyword {
+    // masks from winmeta.xml
+    pub fn mask(&self) -> u64 {
+        match self {
+            Keyword::ResponseTime => 0x0001_0000_0000_0000,
+            Keyword::WdiContext => 0x0002_0000_0000_0000,
+            Keyword::WdiDiag => 0x0004_0000_0000_0000,
+            Keyword::Sqm => 0x0008_0000_0000_0000,
+            Keyword::AuditFailure => 0x0010_0000_0000_0000,
+            Keyword::AuditSuccess => 0x0020_0000_0000_0000,
+            // same bit as AuditFailure in winmeta.xml
+            Keyword::CorrelationHint => 0x0010_0000_0000_0000,
+            Keyword::Classic | Keyword::EventLogClassic => 0x0080_0000_0000_0000,
+            Keyword::Mask(mask) => *mask,
+        }
+    }
+
+    // keyword of name, Audit Success and AuditSuccess are the same
+    fn from_name(name: &str) -> Option<Keyword> {
+        let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+        KEYWORDS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(&name))
+            .map(|(_, k)| k.clone())
+    }
+}
+
+impl FromStr for Keyword {
+    type Err = Error;
+
+    // keyword name, or mask in decimal or 0x hex
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let mask = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse::<u64>().ok(),
+        };
+
+        match mask {
+            Some(mask) => Ok(Keyword::Mask(mask)),
+            None => Keyword::from_name(s).ok_or_else(|| Error {
+                kind: ErrorKind::Filter,
+                message: format!("unknown keyword '{}'", s),
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for Keyword {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match KEYWORDS.iter().find(|(_, k)| k == self) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "0x{:x}", self.mask()),
+        }
+    }
+}
+
+impl From<Keyword> for String {
+    fn from(keyword: Keyword) -> Self {
+        keyword.to_string()
+    }
+}
+
+// DataFilter compares named EventData value, or UserData field when
+// user_data is set. Event matches when all of its data filters match.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DataFilter {
+    pub name: String,
+    #[serde(default)]
+    pub operator: Comparison,
+    pub value: String,
+    #[serde(default)]
+    pub user_data: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Comparison {
+    #[default]
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn operator(self) -> Operator {
+        match self {
+            Comparison::Equal => Operator::Eq,
+            Comparison::NotEqual => Operator::Ne,
+            Comparison::Less => Operator::Lt,
+            Comparison::LessOrEqual => Operator::Le,
+            Comparison::Greater => Operator::Gt,
+            Comparison::GreaterOrEqual => Operator::Ge,
+        }
+    }
+}
+
+impl DataFilter {
+    // numbers are compared by value, anything else as string
+    fn literal(&self) -> Expr {
+        match self.value.parse::<u64>() {
+            Ok(n) if self.value.chars().all(|c| c.is_ascii_digit()) => Expr::Number(n),
+            _ => Expr::String(self.value.clone()),
+        }
+    }
+
+    // field exists and its value compares true, same as xpath comparison
+    fn matches<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(&self, fields: I) -> bool {
+        let literal = self.literal();
+        fields.into_iter().any(|(name, value)| {
+            name == self.name && compare_text(value, self.operator.operator(), &literal)
+        })
+    }
+}
+
+// event fields config is evaluated against
+struct Fields<'a> {
+    channel: &'a str,
+    provider: Option<&'a str>,
+    user: Option<&'a str>,
+    level: Option<u8>,
+    keywords: Option<u64>,
+    event_id: u32,
+    system_time: Option<DateTime<Utc>>,
+    event_data: Vec<(&'a str, &'a str)>,
+    user_data: Vec<(&'a str, &'a str)>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -32,116 +233,1662 @@ impl Level {
             Level::Verbose => "Level=5".into(),
         }
     }
+
+    pub fn number(&self) -> u8 {
+        match *self {
+            Level::LogAlways => 0,
+            Level::Critical => 1,
+            Level::Error => 2,
+            Level::Warning => 3,
+            Level::Information => 4,
+            Level::Verbose => 5,
+        }
+    }
 }
 
-pub fn build_query(filters: Vec<Config>) -> String {
-    let mut queries: Vec<String> = Vec::new();
+// EventIds is parsed event_id expression, 1,3,5-99,-76,-300-400 includes
+// ids 1, 3 and 5 to 99 except 76 and excludes ids 300 to 400
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EventIds {
+    pub include: Vec<RangeInclusive<u32>>,
+    pub exclude: Vec<RangeInclusive<u32>>,
+}
 
-    filters.iter().for_each(|f| {
-        let mut filters: Vec<String> = Vec::new();
+impl EventIds {
+    pub fn parse(ids: &str) -> Result<Self> {
+        let mut event_ids = EventIds::default();
+        // token and position of include ranges, used to report contradictions
+        let mut included = Vec::new();
 
-        if let Some(providers) = f.provider.as_ref() {
-            filters.push(build_provider(providers));
+        if ids.trim().is_empty() {
+            return Ok(event_ids);
         }
 
-        if let Some(levels) = f.level.as_ref() {
-            filters.push(build_level(levels));
+        let mut pos = 0;
+        for part in ids.split(',') {
+            let start = pos + part.len() - part.trim_start().len();
+            pos += part.len() + 1;
+
+            let token = part.trim();
+            let (exclude, id) = match token.strip_prefix('-') {
+                Some(id) => (true, id.trim_start()),
+                None => (false, token),
+            };
+
+            let range = parse_range(id).map_err(|reason| event_id_error(token, start, reason))?;
+
+            if exclude {
+                event_ids.exclude.push(range);
+            } else {
+                event_ids.include.push(range);
+                included.push((token, start));
+            }
         }
 
-        if let Some(ids) = f.event_id.as_ref() {
-            filters.push(build_include_event_id(ids));
+        for (range, (token, start)) in event_ids.include.iter().zip(included) {
+            if is_covered(range, &event_ids.exclude) {
+                return Err(event_id_error(token, start, "all ids are excluded"));
+            }
         }
 
-        if let Some(sec) = f.ignore_older {
-            filters.push(build_ignore_old(sec));
+        Ok(event_ids)
+    }
+
+    // empty include list selects every event id
+    pub fn matches(&self, id: u32) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|r| r.contains(&id)))
+            && !self.exclude.iter().any(|r| r.contains(&id))
+    }
+}
+
+impl fmt::Display for EventIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let include = self.include.iter().map(|r| format_range(r, ""));
+        let exclude = self.exclude.iter().map(|r| format_range(r, "-"));
+        let ids: Vec<String> = include.chain(exclude).collect();
+
+        write!(f, "{}", ids.join(","))
+    }
+}
+
+impl FromStr for EventIds {
+    type Err = Error;
+
+    fn from_str(ids: &str) -> Result<Self> {
+        EventIds::parse(ids)
+    }
+}
+
+// 4624 or 4624-4634
+fn parse_range(id: &str) -> std::result::Result<RangeInclusive<u32>, &'static str> {
+    let mut parts = id.splitn(2, '-');
+    let start = parse_id(parts.next().unwrap_or_default())?;
+
+    match parts.next() {
+        Some(end) => {
+            let end = parse_id(end)?;
+            if start > end {
+                return Err("range start is greater than end");
+            }
+            Ok(start..=end)
         }
+        None => Ok(start..=start),
+    }
+}
+
+fn parse_id(id: &str) -> std::result::Result<u32, &'static str> {
+    let id = id.trim();
 
-        let mut filter_str: String = String::new();
+    if id.is_empty() {
+        return Err("missing id");
+    }
+    if !id.chars().all(|c| c.is_ascii_digit()) {
+        return Err("id is not a number");
+    }
+    id.parse::<u32>().map_err(|_| "id is too large")
+}
 
-        if filters.len() > 0 {
-            filter_str = format!("[System[{}]]", filters.join(" and "))
+// true when every id of range is in one of the excluded ranges
+fn is_covered(range: &RangeInclusive<u32>, exclude: &[RangeInclusive<u32>]) -> bool {
+    let mut next = *range.start() as u64;
+
+    while next <= *range.end() as u64 {
+        match exclude.iter().find(|r| r.contains(&(next as u32))) {
+            Some(r) => next = *r.end() as u64 + 1,
+            None => return false,
         }
+    }
+    true
+}
 
-        queries.push(format!(
-            "<Select Path=\"{}\">*{}</Select>",
-            f.channel, filter_str
-        ));
+fn format_range(range: &RangeInclusive<u32>, prefix: &str) -> String {
+    if range.start() == range.end() {
+        format!("{}{}", prefix, range.start())
+    } else {
+        format!("{}{}-{}", prefix, range.start(), range.end())
+    }
+}
 
-        if let Some(ids) = f.event_id.as_ref() {
-            queries.push(format!(
-                "<Suppress Path=\"{}\">*[System[{}]]</Suppress>",
-                f.channel,
-                build_exclude_event_id(ids)
-            ))
+fn event_id_error(token: &str, position: usize, reason: &str) -> Error {
+    Error {
+        kind: ErrorKind::Filter,
+        message: format!(
+            "invalid event id '{}' at position {} - {}",
+            token, position, reason
+        ),
+    }
+}
+
+impl Config {
+    // parsed event_id expression
+    pub fn event_ids(&self) -> Result<Option<EventIds>> {
+        self.event_id.as_deref().map(EventIds::parse).transpose()
+    }
+
+    // evaluates config against event the same way windows evaluates query
+    // built from it. Config with invalid event_id matches no event.
+    pub fn matches(&self, event: &Event) -> bool {
+        // typed values are compared in their text form, items keep data
+        // with duplicate names which the event_data map doesn't
+        let event_data: Vec<(&str, String)> = event
+            .event_data_items
+            .iter()
+            .filter_map(|d| match (&d.name, &d.value) {
+                (Some(n), v) => Some((
+                    n.as_str(),
+                    v.as_ref().map(|v| v.to_string()).unwrap_or_default(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        self.matches_fields(&Fields {
+            channel: &event.channel,
+            provider: Some(&event.provider_name),
+            user: event.user.get("identifier").and_then(|u| u.as_str()),
+            level: Some(event.level_number),
+            keywords: Some(event.keywords_mask),
+            event_id: event.event_id,
+            system_time: event.system_time,
+            event_data: event_data.iter().map(|(n, v)| (*n, v.as_str())).collect(),
+            // fields with text values, nested ones have no name in UserData/*/
+            user_data: event
+                .user_data
+                .iter()
+                .filter_map(|(n, v)| v.as_str().map(|v| (n.as_str(), v)))
+                .collect(),
+        })
+    }
+
+    pub fn matches_raw(&self, event: &RawEvent) -> bool {
+        let system_time = event
+            .system
+            .time_created
+            .system_time
+            .as_ref()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc));
+
+        let event_data = event
+            .event_data
+            .iter()
+            .flat_map(|e| e.data.iter().flatten())
+            .filter_map(|d| match (&d.name, &d.value) {
+                (Some(n), v) => Some((n.as_str(), v.as_deref().unwrap_or_default())),
+                _ => None,
+            })
+            .collect();
+
+        // children of payload elements, UserData/*/name
+        let user_data: Vec<(&str, String)> = event
+            .user_data
+            .iter()
+            .flat_map(|u| u.children.iter().flat_map(|p| p.children.iter()))
+            .map(|f| (f.name.as_str(), f.value()))
+            .collect();
+
+        self.matches_fields(&Fields {
+            channel: &event.system.channel,
+            provider: event.system.provider.name.as_deref(),
+            user: event.system.security.user_id.as_deref(),
+            level: Some(event.system.level),
+            keywords: u64::from_str_radix(event.system.keywords.trim_start_matches("0x"), 16).ok(),
+            event_id: event.system.event_id.id,
+            system_time,
+            event_data,
+            user_data: user_data.iter().map(|(n, v)| (*n, v.as_str())).collect(),
+        })
+    }
+
+    fn matches_fields(&self, event: &Fields) -> bool {
+        // windows compares channel and provider names case insensitively
+        if !self.channel.eq_ignore_ascii_case(event.channel) {
+            return false;
+        }
+
+        // empty lists are left out of the query as well
+        if let Some(providers) = self.provider.as_ref().filter(|p| !p.is_empty()) {
+            match event.provider {
+                Some(name) if providers.iter().any(|p| p.eq_ignore_ascii_case(name)) => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(levels) = self.level.as_ref().filter(|l| !l.is_empty()) {
+            match event.level {
+                Some(level) if match_level(levels, level) => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(mask) = self.keywords.as_ref().and_then(|k| keywords_mask(k)) {
+            match event.keywords {
+                Some(keywords) if keywords & mask != 0 => (),
+                _ => return false,
+            }
+        }
+
+        match self.event_ids() {
+            Ok(Some(ids)) if !ids.matches(event.event_id) => return false,
+            Err(_) => return false,
+            _ => (),
+        }
+
+        // compared in milliseconds like timediff of the query, large values
+        // saturate instead of overflowing
+        if let Some(sec) = self.ignore_older {
+            match event.system_time {
+                Some(t) if age_millis(t) <= sec.saturating_mul(1000) => (),
+                _ => return false,
+            }
+        }
+
+        // query has time window in milliseconds
+        if self.since.is_some() || self.until.is_some() {
+            let since = self.since.map(|t| t.trunc_subsecs(3));
+            let until = self.until.map(|t| t.trunc_subsecs(3));
+
+            match event.system_time {
+                Some(t) if since.unwrap_or(t) <= t && t <= until.unwrap_or(t) => (),
+                _ => return false,
+            }
+        }
+
+        for filter in self.event_data.iter().flatten() {
+            let fields = if filter.user_data {
+                &event.user_data
+            } else {
+                &event.event_data
+            };
+            if !filter.matches(fields.iter().copied()) {
+                return false;
+            }
+        }
+
+        match self.exclude.as_ref() {
+            Some(exclude) => !exclude.matches(event),
+            None => true,
+        }
+    }
+}
+
+impl Exclude {
+    // true when event is excluded
+    fn matches(&self, event: &Fields) -> bool {
+        let provider = match (self.provider.as_ref(), event.provider) {
+            (Some(providers), Some(name)) => providers.iter().any(|p| p.eq_ignore_ascii_case(name)),
+            _ => false,
+        };
+
+        let level = match (self.level.as_ref(), event.level) {
+            (Some(levels), Some(level)) => match_level(levels, level),
+            _ => false,
+        };
+
+        let user = match (self.user.as_ref(), event.user) {
+            (Some(users), Some(sid)) => users.iter().any(|u| u.eq_ignore_ascii_case(sid)),
+            _ => false,
+        };
+
+        let event_data = match self.event_data.as_ref().filter(|d| !d.is_empty()) {
+            Some(filters) => filters.iter().all(|f| {
+                let fields = if f.user_data {
+                    &event.user_data
+                } else {
+                    &event.event_data
+                };
+                f.matches(fields.iter().copied())
+            }),
+            None => false,
+        };
+
+        provider || level || user || event_data
+    }
+}
+
+// QueryBuilder builds a Config in code, ids and levels are typed so only a range
+// with start greater than end is left for build_query to report
+// let config = QueryBuilder::channel("Security")
+//     .event_ids(4624..=4634)
+//     .exclude_event(4627)
+//     .level_at_least(Level::Warning)
+//     .build();
+#[derive(Clone, Debug, Default)]
+pub struct QueryBuilder {
+    config: Config,
+    event_ids: EventIds,
+}
+
+impl QueryBuilder {
+    pub fn channel(channel: &str) -> Self {
+        QueryBuilder {
+            config: Config {
+                channel: channel.to_owned(),
+                ..Default::default()
+            },
+            event_ids: EventIds::default(),
+        }
+    }
+
+    pub fn event_id(self, id: u32) -> Self {
+        self.event_ids(id..=id)
+    }
+
+    pub fn event_ids(mut self, ids: RangeInclusive<u32>) -> Self {
+        self.event_ids.include.push(ids);
+        self
+    }
+
+    pub fn exclude_event(self, id: u32) -> Self {
+        self.exclude_events(id..=id)
+    }
+
+    pub fn exclude_events(mut self, ids: RangeInclusive<u32>) -> Self {
+        self.event_ids.exclude.push(ids);
+        self
+    }
+
+    pub fn level(mut self, level: Level) -> Self {
+        add_level(self.config.level.get_or_insert_with(Vec::new), level);
+        self
+    }
+
+    // Warning selects warning, error and critical events
+    pub fn level_at_least(mut self, level: Level) -> Self {
+        let levels = self.config.level.get_or_insert_with(Vec::new);
+
+        for l in levels_at_least(&level) {
+            add_level(levels, l);
+        }
+        self
+    }
+
+    pub fn provider(mut self, provider: &str) -> Self {
+        self.config
+            .provider
+            .get_or_insert_with(Vec::new)
+            .push(provider.to_owned());
+        self
+    }
+
+    pub fn keyword(mut self, keyword: Keyword) -> Self {
+        self.config
+            .keywords
+            .get_or_insert_with(Vec::new)
+            .push(keyword);
+        self
+    }
+
+    pub fn ignore_older(mut self, secs: u64) -> Self {
+        self.config.ignore_older = Some(secs);
+        self
+    }
+
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.config.since = Some(since);
+        self
+    }
+
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.config.until = Some(until);
+        self
+    }
+
+    pub fn event_data(mut self, name: &str, operator: Comparison, value: &str) -> Self {
+        self.config
+            .event_data
+            .get_or_insert_with(Vec::new)
+            .push(data_filter(name, operator, value, false));
+        self
+    }
+
+    pub fn user_data(mut self, name: &str, operator: Comparison, value: &str) -> Self {
+        self.config
+            .event_data
+            .get_or_insert_with(Vec::new)
+            .push(data_filter(name, operator, value, true));
+        self
+    }
+
+    pub fn exclude_provider(mut self, provider: &str) -> Self {
+        self.exclude()
+            .provider
+            .get_or_insert_with(Vec::new)
+            .push(provider.to_owned());
+        self
+    }
+
+    pub fn exclude_level(mut self, level: Level) -> Self {
+        add_level(self.exclude().level.get_or_insert_with(Vec::new), level);
+        self
+    }
+
+    // user SID, S-1-5-18
+    pub fn exclude_user(mut self, sid: &str) -> Self {
+        self.exclude()
+            .user
+            .get_or_insert_with(Vec::new)
+            .push(sid.to_owned());
+        self
+    }
+
+    pub fn exclude_event_data(mut self, name: &str, operator: Comparison, value: &str) -> Self {
+        self.exclude()
+            .event_data
+            .get_or_insert_with(Vec::new)
+            .push(data_filter(name, operator, value, false));
+        self
+    }
+
+    pub fn build(self) -> Config {
+        let mut config = self.config;
+
+        if !self.event_ids.include.is_empty() || !self.event_ids.exclude.is_empty() {
+            config.event_id = Some(self.event_ids.to_string());
         }
-    });
 
-    format!(
-        "<QueryList><Query Id=\"0\">{}</Query></QueryList>",
-        queries.join("")
-    )
-    .into()
+        config
+    }
+
+    fn exclude(&mut self) -> &mut Exclude {
+        self.config.exclude.get_or_insert_with(Exclude::default)
+    }
+}
+
+impl From<QueryBuilder> for Config {
+    fn from(query: QueryBuilder) -> Self {
+        query.build()
+    }
+}
+
+fn data_filter(name: &str, operator: Comparison, value: &str, user_data: bool) -> DataFilter {
+    DataFilter {
+        name: name.to_owned(),
+        operator,
+        value: value.to_owned(),
+        user_data,
+    }
+}
+
+// levels from critical up to level, log always only for itself
+fn levels_at_least(level: &Level) -> Vec<Level> {
+    let mut levels = vec![
+        Level::Critical,
+        Level::Error,
+        Level::Warning,
+        Level::Information,
+        Level::Verbose,
+    ];
+    levels.retain(|l| l.number() <= level.number());
+
+    if levels.is_empty() {
+        levels.push(Level::LogAlways);
+    }
+    levels
+}
+
+fn add_level(levels: &mut Vec<Level>, level: Level) {
+    if !levels.iter().any(|l| l.number() == level.number()) {
+        levels.push(level);
+    }
+}
+
+pub fn build_query(filters: Vec<Config>) -> Result<String> {
+    let mut list = QueryList::default();
+
+    // every config is a query of its own, so suppress of one config doesn't
+    // remove events selected by another. Before exclusions all selects were
+    // in one <Query Id="0">, now config i is <Query Id="i">, an event is
+    // selected when any query selects it so the selected events are the same
+    for (i, f) in filters.iter().enumerate() {
+        let mut query = query::Query {
+            id: i as u32,
+            ..Default::default()
+        };
+
+        let ids = f.event_ids()?;
+        let time = build_time(f.since, f.until)?;
+        let data = match f.event_data.as_ref() {
+            Some(filters) => build_data(filters)?,
+            None => Vec::new(),
+        };
+        let (include, exclude) = match ids {
+            Some(ids) => (build_event_id(&ids.include), build_event_id(&ids.exclude)),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        // selects of one config are or'ed, ids which don't fit in one
+        // expression are spread over several selects with same conditions
+        let include: Vec<Option<Expr>> = if include.is_empty() {
+            vec![None]
+        } else {
+            include.into_iter().map(Some).collect()
+        };
+
+        for ids in include {
+            let mut filters: Vec<Expr> = Vec::new();
+
+            if let Some(providers) = f.provider.as_ref() {
+                filters.extend(build_provider(providers));
+            }
+
+            if let Some(levels) = f.level.as_ref() {
+                filters.extend(build_level(levels));
+            }
+
+            if let Some(keywords) = f.keywords.as_ref() {
+                filters.extend(build_keywords(keywords));
+            }
+
+            filters.extend(ids);
+
+            if let Some(sec) = f.ignore_older {
+                filters.push(build_ignore_old(sec));
+            }
+
+            filters.extend(time.clone());
+
+            // *[System[..]] and *[EventData[..]]
+            let mut select: Vec<Expr> = Vec::new();
+
+            if let Some(system) = Expr::join(Operator::And, filters) {
+                select.push(build_system(system));
+            }
+
+            select.extend(data.iter().cloned());
+
+            query.selectors.push(Selector {
+                action: Action::Select,
+                path: Some(f.channel.clone()),
+                expression: Expr::join(Operator::And, select).unwrap_or_else(|| Expr::path(&["*"])),
+            });
+        }
+
+        let mut suppress: Vec<Expr> = exclude.into_iter().map(build_system).collect();
+
+        if let Some(exclude) = f.exclude.as_ref() {
+            suppress.extend(build_exclude(exclude)?);
+        }
+
+        for expression in suppress {
+            query.selectors.push(Selector {
+                action: Action::Suppress,
+                path: Some(f.channel.clone()),
+                expression,
+            })
+        }
+
+        list.queries.push(query);
+    }
+
+    Ok(list.to_xml())
+}
+
+// *[System[..]]
+fn build_system(filter: Expr) -> Expr {
+    Expr::path(&["*"]).with_predicate(Expr::path(&["System"]).with_predicate(filter))
+}
+
+// one suppress expression for each kind of exclusion
+fn build_exclude(exclude: &Exclude) -> Result<Vec<Expr>> {
+    let mut suppress: Vec<Expr> = Vec::new();
+
+    if let Some(providers) = exclude.provider.as_ref() {
+        suppress.extend(build_provider(providers).map(build_system));
+    }
+
+    if let Some(levels) = exclude.level.as_ref() {
+        suppress.extend(build_level(levels).map(build_system));
+    }
+
+    if let Some(users) = exclude.user.as_ref() {
+        suppress.extend(build_user(users)?.map(build_system));
+    }
+
+    if let Some(filters) = exclude.event_data.as_ref() {
+        suppress.extend(Expr::join(Operator::And, build_data(filters)?));
+    }
+
+    Ok(suppress)
+}
+
+// Security[@UserID='S-1-5-18' or @UserID='S-1-5-19']
+fn build_user(users: &[String]) -> Result<Option<Expr>> {
+    let mut s: Vec<Expr> = Vec::new();
+
+    for user in users {
+        check_literal(user)?;
+        s.push(Expr::path(&["@UserID"]).binary(Operator::Eq, Expr::String(user.clone())));
+    }
+
+    Ok(Expr::join(Operator::Or, s).map(|users| Expr::path(&["Security"]).with_predicate(users)))
+}
+
+// *[EventData[Data[@Name='LogonType']=10 and Data[@Name='TargetUserName']!='SYSTEM']]
+// *[UserData/*/PrinterName='MyPrinter']
+fn build_data(filters: &[DataFilter]) -> Result<Vec<Expr>> {
+    let mut event_data: Vec<Expr> = Vec::new();
+    let mut user_data: Vec<Expr> = Vec::new();
+
+    for f in filters {
+        check_literal(&f.value)?;
+
+        if f.user_data {
+            if !is_name(&f.name) {
+                return Err(Error {
+                    kind: ErrorKind::Filter,
+                    message: format!("invalid user data field name '{}'", f.name),
+                });
+            }
+            user_data.push(
+                Expr::path(&["UserData", "*", &f.name]).binary(f.operator.operator(), f.literal()),
+            );
+        } else {
+            check_literal(&f.name)?;
+            let data = Expr::path(&["Data"]).with_predicate(
+                Expr::path(&["@Name"]).binary(Operator::Eq, Expr::String(f.name.clone())),
+            );
+            event_data.push(data.binary(f.operator.operator(), f.literal()));
+        }
+    }
+
+    let event_data =
+        Expr::join(Operator::And, event_data).map(|e| Expr::path(&["EventData"]).with_predicate(e));
+    let user_data = Expr::join(Operator::And, user_data);
+
+    Ok(event_data
+        .into_iter()
+        .chain(user_data)
+        .map(|e| Expr::path(&["*"]).with_predicate(e))
+        .collect())
+}
+
+// xpath strings have no escapes, value can't use both quotes
+fn check_literal(value: &str) -> Result<()> {
+    if value.contains('\'') && value.contains('"') {
+        return Err(Error {
+            kind: ErrorKind::Filter,
+            message: format!("value with both quote characters '{}'", value),
+        });
+    }
+    Ok(())
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+// band(Keywords,13510798882111488), any of the keywords is set
+fn build_keywords(keywords: &[Keyword]) -> Option<Expr> {
+    keywords_mask(keywords).map(|mask| {
+        Expr::Function(
+            "band".into(),
+            vec![Expr::path(&["Keywords"]), Expr::Number(mask)],
+        )
+    })
+}
+
+// combined mask, None when there are no keywords
+fn keywords_mask(keywords: &[Keyword]) -> Option<u64> {
+    keywords.iter().map(|k| k.mask()).reduce(|mask, k| mask | k)
 }
 
 // Provider[@Name='.NET Runtime Optimization Service' or @Name='Microsoft-Windows-All-User-Install-Agent']
-fn build_provider(providers: &Vec<String>) -> String {
-    let s: Vec<String> = providers.iter().map(|p| format!("@Name='{}'", p)).collect();
+fn build_provider(providers: &[String]) -> Option<Expr> {
+    let s: Vec<Expr> = providers
+        .iter()
+        .map(|p| Expr::path(&["@Name"]).binary(Operator::Eq, Expr::String(p.clone())))
+        .collect();
 
-    format!("Provider[{}]", s.join(" or ")).into()
+    Expr::join(Operator::Or, s).map(|names| Expr::path(&["Provider"]).with_predicate(names))
 }
 
-fn build_ignore_old(sec: u64) -> String {
-    return format!("TimeCreated[timediff(@SystemTime) &lt;= {}]", sec * 1000);
+// TimeCreated[timediff(@SystemTime) <= 43200000]
+fn build_ignore_old(sec: u64) -> Expr {
+    let diff = Expr::Function("timediff".into(), vec![Expr::path(&["@SystemTime"])]);
+
+    Expr::path(&["TimeCreated"])
+        .with_predicate(diff.binary(Operator::Le, Expr::Number(sec.saturating_mul(1000))))
+}
+
+// milliseconds since time, events from the future have no age
+fn age_millis(time: DateTime<Utc>) -> u64 {
+    let age = Utc::now().signed_duration_since(time).num_milliseconds();
+    age.max(0) as u64
+}
+
+// TimeCreated[@SystemTime >= '2021-03-01T00:00:00.000Z' and @SystemTime <= '2021-03-02T00:00:00.000Z']
+fn build_time(since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> Result<Option<Expr>> {
+    let format = |t: DateTime<Utc>| {
+        t.trunc_subsecs(3)
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+    };
+
+    // an empty window would silently select nothing
+    if let (Some(since), Some(until)) = (since, until) {
+        if since > until {
+            return Err(Error {
+                kind: ErrorKind::Filter,
+                message: format!(
+                    "invalid time window - since '{}' is after until '{}'",
+                    format(since),
+                    format(until)
+                ),
+            });
+        }
+    }
+
+    let time = |op: Operator, t: DateTime<Utc>| {
+        Expr::path(&["@SystemTime"]).binary(op, Expr::String(format(t)))
+    };
+
+    let window: Vec<Expr> = since
+        .map(|t| time(Operator::Ge, t))
+        .into_iter()
+        .chain(until.map(|t| time(Operator::Le, t)))
+        .collect();
+
+    Ok(Expr::join(Operator::And, window).map(|w| Expr::path(&["TimeCreated"]).with_predicate(w)))
 }
 
 // (Level=1 or Level=3 or Level=4 or Level=0)
-fn build_level(levels: &Vec<Level>) -> String {
-    let mut s: Vec<String> = levels.iter().map(|l| l.value()).collect();
+fn build_level(levels: &[Level]) -> Option<Expr> {
+    let mut s: Vec<u8> = levels.iter().map(|l| l.number()).collect();
 
     // windows event filter includes level 0 when info is selected
-    if s.contains(&"Level=4".to_string()) {
-        s.push("Level=0".into())
+    if s.contains(&4) {
+        s.push(0)
+    }
+
+    let s: Vec<Expr> = s
+        .into_iter()
+        .map(|l| Expr::path(&["Level"]).binary(Operator::Eq, Expr::Number(l as u64)))
+        .collect();
+
+    Expr::join(Operator::Or, s).map(|levels| levels.group())
+}
+
+// (EventID=2 or EventID=4 or (EventID >= 7 and EventID <= 80)),
+// adjacent ids are merged into ranges and split into several expressions
+// when there are more comparisons than windows accepts in one expression
+fn build_event_id(ranges: &[RangeInclusive<u32>]) -> Vec<Expr> {
+    let mut chunks: Vec<Vec<Expr>> = Vec::new();
+    let mut size = MAX_EVENT_ID_EXPRESSIONS;
+
+    for range in merge_ranges(ranges) {
+        let id = || Expr::path(&["EventID"]);
+        let (start, end) = (*range.start() as u64, *range.end() as u64);
+
+        let (e, count) = if start == end {
+            (id().binary(Operator::Eq, Expr::Number(start)), 1)
+        } else {
+            let e = id()
+                .binary(Operator::Ge, Expr::Number(start))
+                .binary(Operator::And, id().binary(Operator::Le, Expr::Number(end)))
+                .group();
+            (e, 2)
+        };
+
+        if size + count > MAX_EVENT_ID_EXPRESSIONS {
+            chunks.push(Vec::new());
+            size = 0;
+        }
+        size += count;
+        chunks.last_mut().unwrap().push(e);
     }
 
-    format!("({})", s.join(" or ")).into()
+    chunks
+        .into_iter()
+        .filter_map(|ids| Expr::join(Operator::Or, ids).map(|e| e.group()))
+        .collect()
 }
 
-fn build_include_event_id(ids: &str) -> String {
-    build_event_id(ids.split(",").filter(|i| !i.starts_with("-")).collect())
+// sorted ranges with overlapping and adjacent ranges merged, 1,2,3-5 is 1-5
+fn merge_ranges(ranges: &[RangeInclusive<u32>]) -> Vec<RangeInclusive<u32>> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_by_key(|r| (*r.start(), *r.end()));
+
+    let mut merged: Vec<RangeInclusive<u32>> = Vec::new();
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if *range.start() as u64 <= *last.end() as u64 + 1 => {
+                if range.end() > last.end() {
+                    *last = *last.start()..=*range.end();
+                }
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
 }
 
-fn build_exclude_event_id(ids: &str) -> String {
-    build_event_id(
-        ids.split(",")
-            .filter(|i| i.starts_with("-"))
-            .map(|i| i.trim_start_matches("-"))
-            .collect(),
-    )
+fn match_level(levels: &[Level], level: u8) -> bool {
+    levels.iter().any(|l| {
+        // same as build_level, info also selects level 0
+        l.number() == level || (level == 0 && l.number() == 4)
+    })
 }
 
-// (EventID=2 or EventID=4 or EventID=5 or  (EventID &gt;= 7 and EventID &lt;= 80))
-fn build_event_id(t: Vec<&str>) -> String {
-    let mut s: Vec<String> = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
 
-    t.iter().for_each(|id| {
-        // check if string is a valid id u32
-        if let Ok(i) = id.parse::<u32>() {
-            s.push(format!("EventID={}", i));
+    fn event_at(time: DateTime<Utc>) -> Event {
+        Event {
+            channel: "Security".to_owned(),
+            event_id: 4624,
+            system_time: Some(time),
+            ..Default::default()
         }
+    }
 
-        // Check if string is a range 35-59
-        let ids: Vec<&str> = id.split("-").collect();
-        // validation
-        if ids.len() == 2 && ids[0].parse::<u32>().is_ok() && ids[1].parse::<u32>().is_ok() {
-            let start = ids[0].parse::<u32>().unwrap();
-            let end = ids[1].parse::<u32>().unwrap();
-            if start < end {
-                s.push(format!(
-                    "(EventID &gt;= {} and EventID &lt;= {})",
-                    start, end
-                ));
-            }
+    // Security 4624 at system_time, body follows System
+    fn event_xml(system_time: &str, body: &str) -> String {
+        format!(
+            "<Event><System><Provider Name='p'/><EventID>4624</EventID><Version>0</Version>\
+             <Level>0</Level><Task>0</Task><Opcode>0</Opcode>\
+             <Keywords>0x8020000000000000</Keywords><TimeCreated SystemTime='{}'/>\
+             <EventRecordID>1</EventRecordID><Correlation/>\
+             <Execution ProcessID='1' ThreadID='1'/><Channel>Security</Channel>\
+             <Computer>c</Computer><Security UserID='S-1-5-18'/></System>{}</Event>",
+            system_time, body
+        )
+    }
+
+    // config, its client side matching and the query windows would run
+    // agree on event
+    fn selects(config: &Config, xml: &str) -> bool {
+        let raw = || RawEvent::try_from(xml.to_owned()).unwrap();
+        let query = QueryList::parse(&build_query(vec![config.clone()]).unwrap()).unwrap();
+        let selected = query.matches(&raw());
+        assert_eq!(config.matches_raw(&raw()), selected, "matches_raw {}", xml);
+        assert_eq!(
+            config.matches(&Event::from_raw(raw(), Default::default())),
+            selected,
+            "matches {}",
+            xml
+        );
+        selected
+    }
+
+    fn event_id_error_message(ids: &str) -> String {
+        EventIds::parse(ids).unwrap_err().message
+    }
+
+    #[test]
+    fn event_ids() {
+        let ids = EventIds::parse("1,3,5-99,-76,-300-400").unwrap();
+        assert_eq!(ids.include, vec![1..=1, 3..=3, 5..=99]);
+        assert_eq!(ids.exclude, vec![76..=76, 300..=400]);
+        assert_eq!(ids.to_string(), "1,3,5-99,-76,-300-400");
+
+        assert!(ids.matches(5) && ids.matches(75) && !ids.matches(76));
+        assert!(!ids.matches(2) && !ids.matches(350));
+
+        // only exclusions select every other id
+        let ids = EventIds::parse("-4624").unwrap();
+        assert!(ids.matches(4625) && !ids.matches(4624));
+
+        assert_eq!(EventIds::parse("").unwrap(), EventIds::default());
+        assert_eq!(EventIds::parse("  ").unwrap(), EventIds::default());
+    }
+
+    #[test]
+    fn event_ids_whitespace() {
+        let ids = EventIds::parse(" 4624 ,\t4625 - 4634 , - 4700 ,-4701- 4702").unwrap();
+        assert_eq!(ids.include, vec![4624..=4624, 4625..=4634]);
+        assert_eq!(ids.exclude, vec![4700..=4700, 4701..=4702]);
+        assert_eq!(ids.to_string(), "4624,4625-4634,-4700,-4701-4702");
+    }
+
+    #[test]
+    fn event_id_errors() {
+        let cases = [
+            ("4624,46x5", "'46x5' at position 5 - id is not a number"),
+            ("4624,  46x5", "'46x5' at position 7 - id is not a number"),
+            ("4624,,4625", "'' at position 5 - missing id"),
+            ("4624,", "'' at position 5 - missing id"),
+            (
+                "4634-4624",
+                "'4634-4624' at position 0 - range start is greater than end",
+            ),
+            (
+                "1,4294967296",
+                "'4294967296' at position 2 - id is too large",
+            ),
+            ("1,+2", "'+2' at position 2 - id is not a number"),
+            ("1,--2", "'--2' at position 2 - missing id"),
+        ];
+
+        for (ids, message) in cases.iter() {
+            assert_eq!(
+                event_id_error_message(ids),
+                format!("invalid event id {}", message),
+                "{}",
+                ids
+            );
+        }
+    }
+
+    #[test]
+    fn all_ids_excluded() {
+        assert_eq!(
+            event_id_error_message(" 4624 , -4624"),
+            "invalid event id '4624' at position 1 - all ids are excluded"
+        );
+        assert_eq!(
+            event_id_error_message("1,5-10,-5-7,-8-10"),
+            "invalid event id '5-10' at position 2 - all ids are excluded"
+        );
+        assert!(EventIds::parse("5-10,-5-7,-9-10").is_ok());
+        assert!(EventIds::parse("4294967295,-4294967295,1").is_err());
+    }
+
+    // every other id, none of them are merged
+    fn single_ids(count: u32) -> Vec<RangeInclusive<u32>> {
+        (0..count).map(|i| i * 2..=i * 2).collect()
+    }
+
+    fn ranges(count: u32) -> Vec<RangeInclusive<u32>> {
+        (0..count).map(|i| 1000 + i * 10..=1005 + i * 10).collect()
+    }
+
+    // EventID comparisons of each expression
+    fn comparisons(exprs: &[Expr]) -> Vec<usize> {
+        exprs
+            .iter()
+            .map(|e| e.to_string().matches("EventID").count())
+            .collect()
+    }
+
+    #[test]
+    fn merges_ranges() {
+        let merged = merge_ranges(&[
+            5..=9,
+            1..=1,
+            2..=3,
+            10..=10,
+            20..=30,
+            25..=26,
+            31..=31,
+            u32::MAX..=u32::MAX,
+            u32::MAX - 1..=u32::MAX - 1,
+            7..=8,
+        ]);
+        assert_eq!(
+            merged,
+            vec![1..=3, 5..=10, 20..=31, u32::MAX - 1..=u32::MAX]
+        );
+
+        assert!(merge_ranges(&[]).is_empty());
+    }
+
+    #[test]
+    fn event_id_expression() {
+        let exprs = build_event_id(&[7..=80, 4..=4, 2..=2, 81..=81]);
+        assert_eq!(exprs.len(), 1);
+        assert_eq!(
+            exprs[0].to_string(),
+            "(EventID=2 or EventID=4 or (EventID >= 7 and EventID <= 81))"
+        );
+
+        assert!(build_event_id(&[]).is_empty());
+    }
+
+    #[test]
+    fn splits_event_id_expression() {
+        assert_eq!(comparisons(&build_event_id(&single_ids(22))), vec![22]);
+        assert_eq!(comparisons(&build_event_id(&single_ids(23))), vec![22, 1]);
+        assert_eq!(
+            comparisons(&build_event_id(&single_ids(45))),
+            vec![22, 22, 1]
+        );
+
+        // ranges count as two comparisons
+        assert_eq!(comparisons(&build_event_id(&ranges(11))), vec![22]);
+        assert_eq!(comparisons(&build_event_id(&ranges(12))), vec![22, 2]);
+
+        // range which doesn't fit starts next expression
+        let mut ids = single_ids(21);
+        ids.extend(ranges(1));
+        assert_eq!(comparisons(&build_event_id(&ids)), vec![21, 2]);
+
+        // adjacent ids are merged before counting
+        let adjacent: Vec<RangeInclusive<u32>> = (0..100).map(|i| i..=i).collect();
+        assert_eq!(comparisons(&build_event_id(&adjacent)), vec![2]);
+    }
+
+    #[test]
+    fn split_event_ids_select_same_events() {
+        let ids: Vec<String> = (0..30).map(|i| (i * 2).to_string()).collect();
+        let config = QueryBuilder::channel("Security")
+            .level(Level::Warning)
+            .build();
+        let config = Config {
+            event_id: Some(ids.join(",")),
+            ..config
+        };
+
+        let query = QueryList::parse(&build_query(vec![config]).unwrap()).unwrap();
+        let selectors = &query.queries[0].selectors;
+        assert_eq!(selectors.len(), 2);
+        for selector in selectors {
+            assert_eq!(selector.action, Action::Select);
+            assert!(selector.expression.to_string().contains("Level=3"));
+        }
+    }
+
+    #[test]
+    fn parses_keywords() {
+        let cases = [
+            ("AuditFailure", Keyword::AuditFailure),
+            ("Audit Failure", Keyword::AuditFailure),
+            (" correlationhint ", Keyword::CorrelationHint),
+            ("0x10", Keyword::Mask(0x10)),
+            ("0X1f", Keyword::Mask(0x1f)),
+            ("16", Keyword::Mask(16)),
+        ];
+        for (s, keyword) in cases.iter() {
+            assert_eq!(&s.parse::<Keyword>().unwrap(), keyword, "{}", s);
         }
-    });
-    format!("({})", s.join(" or ")).into()
+        assert!("Audit Maybe".parse::<Keyword>().is_err());
+        assert!("0xzz".parse::<Keyword>().is_err());
+
+        assert_eq!(
+            Keyword::CorrelationHint.mask(),
+            Keyword::AuditFailure.mask()
+        );
+    }
+
+    #[test]
+    fn keywords_in_config() {
+        let config: Config = serde_json::from_str(
+            r#"{"channel": "Security", "keywords": ["Audit Failure", "0x10", "Classic"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.keywords,
+            Some(vec![
+                Keyword::AuditFailure,
+                Keyword::Mask(0x10),
+                Keyword::Classic
+            ])
+        );
+
+        let json = serde_json::to_string(&config.keywords).unwrap();
+        assert_eq!(json, r#"["AuditFailure","0x10","Classic"]"#);
+
+        let err =
+            serde_json::from_str::<Config>(r#"{"channel": "Security", "keywords": ["Nope"]}"#)
+                .unwrap_err();
+        assert!(err.to_string().contains("unknown keyword 'Nope'"));
+    }
+
+    #[test]
+    fn keywords_query_and_matching() {
+        let keywords = [Keyword::AuditSuccess, Keyword::Mask(0x8)];
+        assert_eq!(
+            build_keywords(&keywords).unwrap().to_string(),
+            "band(Keywords,9007199254741000)"
+        );
+        assert!(build_keywords(&[]).is_none());
+
+        let config = QueryBuilder::channel("Security")
+            .keyword(Keyword::AuditFailure)
+            .build();
+        let query = build_query(vec![config.clone()]).unwrap();
+        assert!(query.contains("*[System[band(Keywords,4503599627370496)]]"));
+
+        let event = |mask: u64| Event {
+            channel: "Security".to_owned(),
+            keywords_mask: mask,
+            ..Default::default()
+        };
+        assert!(config.matches(&event(0x8010_0000_0000_0000)));
+        assert!(!config.matches(&event(0x8020_0000_0000_0000)));
+
+        let raw = |keywords: &str| {
+            let xml = format!(
+                "<Event><System><Provider Name='p'/><EventID>1</EventID><Version>0</Version>\
+                 <Level>0</Level><Task>0</Task><Opcode>0</Opcode><Keywords>{}</Keywords>\
+                 <TimeCreated/><EventRecordID>1</EventRecordID><Correlation/>\
+                 <Execution ProcessID='1' ThreadID='1'/><Channel>Security</Channel>\
+                 <Computer>c</Computer><Security/></System></Event>",
+                keywords
+            );
+            RawEvent::try_from(xml).unwrap()
+        };
+        assert!(config.matches_raw(&raw("0x8010000000000000")));
+        assert!(!config.matches_raw(&raw("0x8020000000000000")));
+        assert!(!config.matches_raw(&raw("")));
+    }
+
+    #[test]
+    fn data_xpath() {
+        let filters = [
+            data_filter("LogonType", Comparison::Equal, "10", false),
+            data_filter("TargetUserName", Comparison::NotEqual, "O'Brien", false),
+            data_filter("PrinterName", Comparison::Equal, "My \"Printer\"", true),
+        ];
+        let exprs: Vec<String> = build_data(&filters)
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            exprs,
+            vec![
+                "*[EventData[Data[@Name='LogonType']=10 and Data[@Name='TargetUserName']!=\"O'Brien\"]]",
+                "*[UserData/*/PrinterName='My \"Printer\"']",
+            ]
+        );
+
+        let comparisons = [
+            (Comparison::Equal, "="),
+            (Comparison::NotEqual, "!="),
+            (Comparison::Less, " < "),
+            (Comparison::LessOrEqual, " <= "),
+            (Comparison::Greater, " > "),
+            (Comparison::GreaterOrEqual, " >= "),
+        ];
+        for (comparison, operator) in comparisons.iter() {
+            let exprs = build_data(&[data_filter("Count", *comparison, "5", false)]).unwrap();
+            assert_eq!(
+                exprs[0].to_string(),
+                format!("*[EventData[Data[@Name='Count']{}5]]", operator)
+            );
+        }
+
+        // digits only are numbers, anything else is compared as string
+        let exprs = build_data(&[data_filter("Code", Comparison::Equal, "0x10", false)]).unwrap();
+        assert_eq!(
+            exprs[0].to_string(),
+            "*[EventData[Data[@Name='Code']='0x10']]"
+        );
+
+        assert_eq!(
+            build_user(&["S-1-5-18".to_owned(), "S-1-5-19".to_owned()])
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "Security[@UserID='S-1-5-18' or @UserID='S-1-5-19']"
+        );
+        assert!(build_user(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn data_literal_errors() {
+        let error = |result: Result<Vec<Expr>>| {
+            let error = result.unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Filter);
+            error.message
+        };
+        let both = "it's \"both\"";
+        assert!(check_literal("it's").is_ok());
+        assert!(check_literal("\"quoted\"").is_ok());
+        assert_eq!(
+            check_literal(both).unwrap_err().message,
+            "value with both quote characters 'it's \"both\"'"
+        );
+
+        assert_eq!(
+            error(build_data(&[data_filter(
+                "Name",
+                Comparison::Equal,
+                both,
+                false
+            )])),
+            "value with both quote characters 'it's \"both\"'"
+        );
+        assert_eq!(
+            error(build_data(&[data_filter(
+                both,
+                Comparison::Equal,
+                "x",
+                false
+            )])),
+            "value with both quote characters 'it's \"both\"'"
+        );
+        assert_eq!(
+            error(build_data(&[data_filter(
+                "Printer Name",
+                Comparison::Equal,
+                "x",
+                true
+            )])),
+            "invalid user data field name 'Printer Name'"
+        );
+        assert!(build_user(&[both.to_owned()]).is_err());
+
+        let config = QueryBuilder::channel("Security")
+            .event_data("TargetUserName", Comparison::Equal, both)
+            .build();
+        assert_eq!(
+            build_query(vec![config]).unwrap_err().kind,
+            ErrorKind::Filter
+        );
+    }
+
+    #[test]
+    fn data_matching() {
+        let xml = event_xml(
+            "2021-03-01T10:00:00.000Z",
+            "<EventData><Data Name='TargetUserName'>O'Brien</Data>\
+             <Data Name='LogonType'>10</Data><Data Name='Flag'>a</Data>\
+             <Data Name='Flag'>b</Data><Data>unnamed</Data></EventData>",
+        );
+        let data = |name: &str, comparison: Comparison, value: &str| {
+            let config = QueryBuilder::channel("Security")
+                .event_data(name, comparison, value)
+                .build();
+            selects(&config, &xml)
+        };
+
+        assert!(data("LogonType", Comparison::Equal, "10"));
+        assert!(!data("LogonType", Comparison::NotEqual, "10"));
+        assert!(data("LogonType", Comparison::NotEqual, "3"));
+        assert!(data("LogonType", Comparison::Less, "11"));
+        assert!(!data("LogonType", Comparison::Less, "10"));
+        assert!(data("LogonType", Comparison::LessOrEqual, "10"));
+        assert!(data("LogonType", Comparison::Greater, "9"));
+        assert!(!data("LogonType", Comparison::Greater, "10"));
+        assert!(data("LogonType", Comparison::GreaterOrEqual, "10"));
+        assert!(!data("LogonType", Comparison::GreaterOrEqual, "11"));
+
+        assert!(data("TargetUserName", Comparison::Equal, "O'Brien"));
+        // strings compare case insensitively like windows does
+        assert!(data("TargetUserName", Comparison::Equal, "o'brien"));
+        assert!(!data("TargetUserName", Comparison::Equal, "Brien"));
+        // any of the repeated names matches
+        assert!(data("Flag", Comparison::Equal, "b"));
+        assert!(data("Flag", Comparison::NotEqual, "a"));
+        // missing field compares false, != too
+        assert!(!data("Missing", Comparison::NotEqual, "x"));
+
+        let xml = event_xml(
+            "2021-03-01T10:00:00.000Z",
+            "<UserData><Printed xmlns='x'><PrinterName>My \"Printer\"</PrinterName>\
+             <Pages>3</Pages></Printed></UserData>",
+        );
+        let user = |name: &str, comparison: Comparison, value: &str| {
+            let config = QueryBuilder::channel("Security")
+                .user_data(name, comparison, value)
+                .build();
+            selects(&config, &xml)
+        };
+        assert!(user("PrinterName", Comparison::Equal, "My \"Printer\""));
+        assert!(!user("PrinterName", Comparison::Equal, "Other"));
+        assert!(user("Pages", Comparison::Greater, "2"));
+        assert!(!user("Pages", Comparison::Greater, "3"));
+    }
+
+    #[test]
+    fn time_window() {
+        let time = |t: &str| DateTime::parse_from_rfc3339(t).unwrap().with_timezone(&Utc);
+        let since = time("2021-03-01T00:00:00.000Z");
+        let until = time("2021-03-02T00:00:00.123456Z");
+        let xpath = |since, until| build_time(since, until).unwrap().map(|e| e.to_string());
+
+        assert_eq!(
+            xpath(Some(since), None).unwrap(),
+            "TimeCreated[@SystemTime >= '2021-03-01T00:00:00.000Z']"
+        );
+        assert_eq!(
+            xpath(None, Some(until)).unwrap(),
+            "TimeCreated[@SystemTime <= '2021-03-02T00:00:00.123Z']"
+        );
+        assert_eq!(
+            xpath(Some(since), Some(until)).unwrap(),
+            "TimeCreated[@SystemTime >= '2021-03-01T00:00:00.000Z' and \
+             @SystemTime <= '2021-03-02T00:00:00.123Z']"
+        );
+        assert_eq!(xpath(Some(since), Some(since)).unwrap(), "TimeCreated[@SystemTime >= '2021-03-01T00:00:00.000Z' and @SystemTime <= '2021-03-01T00:00:00.000Z']");
+        assert!(xpath(None, None).is_none());
+
+        let at = |t: &str| event_xml(t, "");
+        let config = QueryBuilder::channel("Security").since(since).build();
+        assert!(selects(&config, &at("2021-03-01T00:00:00.000Z")));
+        assert!(selects(&config, &at("2025-01-01T00:00:00.000Z")));
+        assert!(!selects(&config, &at("2021-02-28T23:59:59.999Z")));
+
+        // until is compared in milliseconds, later digits are dropped
+        let config = QueryBuilder::channel("Security").until(until).build();
+        assert!(selects(&config, &at("2020-01-01T00:00:00.000Z")));
+        assert!(selects(&config, &at("2021-03-02T00:00:00.123Z")));
+        assert!(!selects(&config, &at("2021-03-02T00:00:00.124Z")));
+
+        let config = QueryBuilder::channel("Security")
+            .since(since)
+            .until(until)
+            .build();
+        assert!(selects(&config, &at("2021-03-01T12:00:00.000Z")));
+        assert!(!selects(&config, &at("2021-02-28T12:00:00.000Z")));
+        assert!(!selects(&config, &at("2021-03-03T12:00:00.000Z")));
+    }
+
+    #[test]
+    fn time_window_error() {
+        let since = Utc.with_ymd_and_hms(2021, 3, 2, 0, 0, 0).unwrap();
+        let until = Utc.with_ymd_and_hms(2021, 3, 1, 0, 0, 0).unwrap();
+        let config = QueryBuilder::channel("Security")
+            .since(since)
+            .until(until)
+            .build();
+
+        let error = build_query(vec![config]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Filter);
+        assert_eq!(
+            error.message,
+            "invalid time window - since '2021-03-02T00:00:00.000Z' is after until \
+             '2021-03-01T00:00:00.000Z'"
+        );
+
+        // the window is checked however the configs are combined
+        let ok = QueryBuilder::channel("System").build();
+        let bad = QueryBuilder::channel("Security")
+            .since(since)
+            .until(until)
+            .build();
+        assert!(build_query(vec![ok, bad]).is_err());
+    }
+
+    #[test]
+    fn builder_chains() {
+        let since = Utc.with_ymd_and_hms(2021, 3, 1, 0, 0, 0).unwrap();
+        let config = QueryBuilder::channel("Security")
+            .event_id(4624)
+            .event_ids(4630..=4634)
+            .exclude_event(4632)
+            .level_at_least(Level::Warning)
+            .level(Level::Error)
+            .level(Level::Verbose)
+            .provider("Microsoft-Windows-Security-Auditing")
+            .keyword(Keyword::AuditSuccess)
+            .ignore_older(60)
+            .since(since)
+            .event_data("LogonType", Comparison::Equal, "10")
+            .user_data("PrinterName", Comparison::NotEqual, "x")
+            .exclude_provider("p")
+            .exclude_level(Level::Verbose)
+            .exclude_user("S-1-5-18")
+            .exclude_event_data("TargetUserName", Comparison::Equal, "SYSTEM")
+            .build();
+
+        let filter = |name: &str, operator: &str, value: &str, user_data: bool| {
+            serde_json::json!({
+                "name": name, "operator": operator, "value": value, "user_data": user_data
+            })
+        };
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "channel": "Security",
+                "level": ["Critical", "Error", "Warning", "Verbose"],
+                "event_id": "4624,4630-4634,-4632",
+                "ignore_older": 60,
+                "since": "2021-03-01T00:00:00Z",
+                "until": null,
+                "provider": ["Microsoft-Windows-Security-Auditing"],
+                "event_data": [
+                    filter("LogonType", "Equal", "10", false),
+                    filter("PrinterName", "NotEqual", "x", true),
+                ],
+                "keywords": ["AuditSuccess"],
+                "exclude": {
+                    "provider": ["p"],
+                    "level": ["Verbose"],
+                    "user": ["S-1-5-18"],
+                    "event_data": [filter("TargetUserName", "Equal", "SYSTEM", false)],
+                },
+            })
+        );
+        assert!(build_query(vec![config]).is_ok());
+
+        // nothing set selects the whole channel
+        let config: Config = QueryBuilder::channel("System").into();
+        assert!(config.event_id.is_none());
+        assert!(config.exclude.is_none());
+        assert_eq!(
+            build_query(vec![config]).unwrap(),
+            "<QueryList><Query Id=\"0\"><Select Path=\"System\">*</Select></Query></QueryList>"
+        );
+    }
+
+    #[test]
+    fn builder_invalid_ranges() {
+        let error = |builder: QueryBuilder| {
+            let error = build_query(vec![builder.build()]).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Filter);
+            error.message
+        };
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 4634..=4624;
+        assert_eq!(
+            error(QueryBuilder::channel("Security").event_ids(reversed.clone())),
+            "invalid event id '4634-4624' at position 0 - range start is greater than end"
+        );
+        assert_eq!(
+            error(
+                QueryBuilder::channel("Security")
+                    .event_id(1)
+                    .exclude_events(reversed.clone())
+            ),
+            "invalid event id '-4634-4624' at position 2 - range start is greater than end"
+        );
+        assert_eq!(
+            error(
+                QueryBuilder::channel("Security")
+                    .event_ids(10..=20)
+                    .exclude_events(5..=25)
+            ),
+            "invalid event id '10-20' at position 0 - all ids are excluded"
+        );
+        // Config::matches treats the invalid ids as matching no event
+        let config = QueryBuilder::channel("Security")
+            .event_ids(reversed)
+            .build();
+        assert!(!config.matches(&event_at(Utc::now())));
+    }
+
+    #[test]
+    fn exclude_suppress() {
+        let exclude = Exclude {
+            provider: Some(vec!["Microsoft-Windows-Kernel".to_owned()]),
+            level: Some(vec![Level::Verbose, Level::Warning]),
+            user: Some(vec!["S-1-5-18".to_owned()]),
+            event_data: Some(vec![
+                data_filter("LogonType", Comparison::Equal, "5", false),
+                data_filter("PrinterName", Comparison::NotEqual, "x", true),
+            ]),
+        };
+        let suppress: Vec<String> = build_exclude(&exclude)
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            suppress,
+            vec![
+                "*[System[Provider[@Name='Microsoft-Windows-Kernel']]]",
+                "*[System[(Level=5 or Level=3)]]",
+                "*[System[Security[@UserID='S-1-5-18']]]",
+                "*[EventData[Data[@Name='LogonType']=5]] and *[UserData/*/PrinterName!='x']",
+            ]
+        );
+        assert!(build_exclude(&Exclude::default()).unwrap().is_empty());
+
+        let config = QueryBuilder::channel("Security")
+            .event_id(4624)
+            .exclude_user("S-1-5-18")
+            .exclude_level(Level::Verbose)
+            .build();
+        assert_eq!(
+            build_query(vec![config]).unwrap(),
+            "<QueryList><Query Id=\"0\">\
+             <Select Path=\"Security\">*[System[(EventID=4624)]]</Select>\
+             <Suppress Path=\"Security\">*[System[(Level=5)]]</Suppress>\
+             <Suppress Path=\"Security\">*[System[Security[@UserID='S-1-5-18']]]</Suppress>\
+             </Query></QueryList>"
+        );
+    }
+
+    #[test]
+    fn query_per_config() {
+        let security = QueryBuilder::channel("Security")
+            .event_id(4624)
+            .exclude_user("S-1-5-18")
+            .build();
+        let system = QueryBuilder::channel("System").build();
+        let all_security = QueryBuilder::channel("Security")
+            .level(Level::Information)
+            .build();
+
+        assert_eq!(
+            build_query(vec![security.clone(), system.clone()]).unwrap(),
+            "<QueryList><Query Id=\"0\">\
+             <Select Path=\"Security\">*[System[(EventID=4624)]]</Select>\
+             <Suppress Path=\"Security\">*[System[Security[@UserID='S-1-5-18']]]</Suppress>\
+             </Query><Query Id=\"1\"><Select Path=\"System\">*</Select></Query></QueryList>"
+        );
+
+        // suppress of the first config doesn't drop what the last one selects
+        let xml = event_xml("2021-03-01T10:00:00.000Z", "");
+        let event = RawEvent::try_from(xml.clone()).unwrap();
+        assert!(!selects(&security, &xml));
+        assert!(selects(&all_security, &xml));
+        let query = build_query(vec![security, system, all_security]).unwrap();
+        let query = QueryList::parse(&query).unwrap();
+        assert_eq!(query.queries.len(), 3);
+        assert!(query.matches(&event));
+    }
+
+    #[test]
+    fn exclude_matching() {
+        let xml = event_xml(
+            "2021-03-01T10:00:00.000Z",
+            "<EventData><Data Name='TargetUserName'>bob</Data>\
+             <Data Name='LogonType'>10</Data></EventData>",
+        );
+        let excluded = |query: QueryBuilder| !selects(&query.build(), &xml);
+
+        assert!(!excluded(QueryBuilder::channel("Security")));
+        assert!(excluded(
+            QueryBuilder::channel("Security").exclude_provider("P")
+        ));
+        assert!(!excluded(
+            QueryBuilder::channel("Security").exclude_provider("q")
+        ));
+        // level 0 is information, like the query has it
+        assert!(excluded(
+            QueryBuilder::channel("Security").exclude_level(Level::Information)
+        ));
+        assert!(!excluded(
+            QueryBuilder::channel("Security").exclude_level(Level::Warning)
+        ));
+        assert!(excluded(
+            QueryBuilder::channel("Security").exclude_user("s-1-5-18")
+        ));
+        assert!(!excluded(
+            QueryBuilder::channel("Security").exclude_user("S-1-5-19")
+        ));
+
+        // any kind of exclusion removes the event
+        assert!(excluded(
+            QueryBuilder::channel("Security")
+                .exclude_provider("q")
+                .exclude_user("S-1-5-18")
+        ));
+
+        // all data conditions must match
+        let data = QueryBuilder::channel("Security")
+            .exclude_event_data("LogonType", Comparison::Equal, "10")
+            .exclude_event_data("TargetUserName", Comparison::Equal, "bob");
+        assert!(excluded(data));
+        let data = QueryBuilder::channel("Security")
+            .exclude_event_data("LogonType", Comparison::Equal, "10")
+            .exclude_event_data("TargetUserName", Comparison::Equal, "alice");
+        assert!(!excluded(data));
+    }
+
+    #[test]
+    fn ignore_older() {
+        let config = QueryBuilder::channel("Security").ignore_older(3600).build();
+
+        assert!(config.matches(&event_at(Utc::now() - Duration::minutes(30))));
+        assert!(!config.matches(&event_at(Utc::now() - Duration::hours(2))));
+        assert!(config.matches(&event_at(Utc::now() + Duration::minutes(5))));
+        assert!(!config.matches(&Event {
+            system_time: None,
+            ..event_at(Utc::now())
+        }));
+    }
+
+    #[test]
+    fn ignore_older_future_event() {
+        let config = QueryBuilder::channel("Security").ignore_older(60).build();
+        let future = Utc::now() + Duration::hours(2);
+        assert!(config.matches(&event_at(future)));
+
+        let xml = format!(
+            "<Event><System><Provider Name='p'/><EventID>4624</EventID><Version>0</Version>\
+             <Level>0</Level><Task>0</Task><Opcode>0</Opcode><Keywords>0x0</Keywords>\
+             <TimeCreated SystemTime='{}'/><EventRecordID>1</EventRecordID><Correlation/>\
+             <Execution ProcessID='1' ThreadID='1'/><Channel>Security</Channel>\
+             <Computer>c</Computer><Security/></System></Event>",
+            future.to_rfc3339_opts(SecondsFormat::Millis, true)
+        );
+        let event = RawEvent::try_from(xml).unwrap();
+        assert!(config.matches_raw(&event));
+
+        // the query sees a future event as 0 milliseconds old too
+        let query = QueryList::parse(&build_query(vec![config]).unwrap()).unwrap();
+        assert!(query.matches(&event));
+        let expr = Expr::parse("*[System[TimeCreated[timediff(@SystemTime) = 0]]]").unwrap();
+        assert!(expr.matches(&event));
+    }
+
+    #[test]
+    fn ignore_older_saturates() {
+        let config = QueryBuilder::channel("Security")
+            .ignore_older(100_000_000_000_000_000)
+            .build();
+        let old = Utc::now() - Duration::days(365 * 100);
+        assert!(config.matches(&event_at(old)));
+
+        let config = QueryBuilder::channel("Security")
+            .ignore_older(u64::MAX)
+            .build();
+        assert!(config.matches(&event_at(old)));
+
+        let query = build_query(vec![config]).unwrap();
+        assert!(query.contains(&format!("timediff(@SystemTime) &lt;= {}", u64::MAX)));
+    }
 }
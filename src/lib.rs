@@ -1,11 +1,25 @@
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", feature = "reader"))]
 pub mod reader;
 
-#[cfg(target_os = "windows")]
 pub mod event;
 
-#[cfg(target_os = "windows")]
+pub mod binary;
+
 pub mod filter;
 
-#[cfg(target_os = "windows")]
 pub mod error;
+
+pub mod evtx;
+
+pub mod source;
+
+#[cfg(feature = "async")]
+pub mod stream;
+
+pub mod bookmark;
+
+pub mod checkpoint;
+
+pub mod ack;
+
+pub mod query;
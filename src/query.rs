@@ -0,0 +1,254 @@
+mod eval;
+mod xpath;
+
+use crate::error::{Error, ErrorKind, Result};
+
+pub(crate) use eval::compare_text;
+pub use xpath::{Expr, Node, Operator, Step};
+
+use quick_xml::{events::Event as QuickXmlEvent, Reader as QuickXmlReader};
+use std::fmt;
+use std::str::FromStr;
+
+// QueryList is the structured query windows accepts in subscriptions and
+// event viewer custom views:
+// <QueryList>
+//   <Query Id="0" Path="Application">
+//     <Select Path="Application">*[System[(Level=1 or Level=2)]]</Select>
+//     <Suppress Path="Application">*[System[(EventID=1001)]]</Suppress>
+//   </Query>
+// </QueryList>
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueryList {
+    pub queries: Vec<Query>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Query {
+    pub id: u32,
+    // channel used by selectors without path
+    pub path: Option<String>,
+    pub selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    pub action: Action,
+    pub path: Option<String>,
+    pub expression: Expr,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Select,
+    Suppress,
+}
+
+impl Action {
+    fn element(self) -> &'static str {
+        match self {
+            Action::Select => "Select",
+            Action::Suppress => "Suppress",
+        }
+    }
+}
+
+impl Selector {
+    // channel of selector, path of query is used when selector has none
+    pub fn channel<'a>(&'a self, query: &'a Query) -> Option<&'a str> {
+        self.path.as_deref().or(query.path.as_deref())
+    }
+}
+
+impl QueryList {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let mut reader = QuickXmlReader::from_str(xml);
+        reader.trim_text(true);
+
+        let mut list = QueryList::default();
+        let mut buf = Vec::new();
+        let mut has_list = false;
+        // selector waiting for its expression text
+        let mut selector: Option<(Action, Option<String>, String)> = None;
+
+        let mut in_query = false;
+
+        loop {
+            let event = reader.read_event(&mut buf);
+            let empty = matches!(event, Ok(QuickXmlEvent::Empty(_)));
+
+            match event {
+                Ok(QuickXmlEvent::Start(ref e)) | Ok(QuickXmlEvent::Empty(ref e)) => {
+                    let mut id = None;
+                    let mut path = None;
+
+                    for a in e.attributes() {
+                        let att = a.map_err(|e| query_error(&e.to_string()))?;
+                        let value = att
+                            .unescape_and_decode_value(&reader)
+                            .map_err(|e| query_error(&e.to_string()))?;
+
+                        match att.key {
+                            b"Id" => match value.parse::<u32>() {
+                                Ok(v) => id = Some(v),
+                                Err(_) => {
+                                    return Err(query_error(&format!(
+                                        "invalid query id '{}'",
+                                        value
+                                    )))
+                                }
+                            },
+                            b"Path" => path = Some(value),
+                            _ => (),
+                        }
+                    }
+
+                    match (e.name(), list.queries.last_mut()) {
+                        (b"QueryList", _) if !has_list => has_list = true,
+                        (b"Query", _) if has_list && !in_query => {
+                            list.queries.push(Query {
+                                id: id.ok_or_else(|| query_error("query without Id"))?,
+                                path,
+                                selectors: Vec::new(),
+                            });
+                            in_query = !empty;
+                        }
+                        (b"Select", Some(query)) | (b"Suppress", Some(query))
+                            if in_query && selector.is_none() =>
+                        {
+                            if path.is_none() && query.path.is_none() {
+                                return Err(query_error(&format!(
+                                    "selector without Path in query {}",
+                                    query.id
+                                )));
+                            }
+
+                            let action = match e.name() {
+                                b"Select" => Action::Select,
+                                _ => Action::Suppress,
+                            };
+                            if empty {
+                                return Err(query_error(&format!(
+                                    "{} without expression in query {}",
+                                    action.element(),
+                                    query.id
+                                )));
+                            }
+                            selector = Some((action, path, String::new()));
+                        }
+                        (n, _) => {
+                            return Err(query_error(&format!(
+                                "unexpected element '{}'",
+                                String::from_utf8_lossy(n)
+                            )))
+                        }
+                    }
+                }
+                Ok(QuickXmlEvent::Text(ref t)) | Ok(QuickXmlEvent::CData(ref t)) => {
+                    if let Some((_, _, text)) = selector.as_mut() {
+                        let value = t
+                            .unescape_and_decode(&reader)
+                            .map_err(|e| query_error(&e.to_string()))?;
+                        text.push_str(&value);
+                    }
+                }
+                Ok(QuickXmlEvent::End(ref e)) => {
+                    if e.name() == b"Query" {
+                        in_query = false;
+                    } else if e.name() == b"Select" || e.name() == b"Suppress" {
+                        if let (Some((action, path, text)), Some(query)) =
+                            (selector.take(), list.queries.last_mut())
+                        {
+                            query.selectors.push(Selector {
+                                action,
+                                path,
+                                expression: Expr::parse(&text)?,
+                            });
+                        }
+                    }
+                }
+                Ok(QuickXmlEvent::Eof) => break,
+                Err(e) => return Err(query_error(&e.to_string())),
+                _ => (),
+            }
+            buf.clear();
+        }
+
+        if !has_list {
+            return Err(query_error("missing QueryList element"));
+        }
+
+        Ok(list)
+    }
+
+    pub fn to_xml(&self) -> String {
+        let queries: Vec<String> = self.queries.iter().map(|q| q.to_xml()).collect();
+
+        format!("<QueryList>{}</QueryList>", queries.join(""))
+    }
+}
+
+impl Query {
+    fn to_xml(&self) -> String {
+        let path = match &self.path {
+            Some(path) => format!(" Path=\"{}\"", escape_attribute(path)),
+            None => "".to_owned(),
+        };
+
+        let selectors: Vec<String> = self
+            .selectors
+            .iter()
+            .map(|s| {
+                let path = match &s.path {
+                    Some(path) => format!(" Path=\"{}\"", escape_attribute(path)),
+                    None => "".to_owned(),
+                };
+                format!(
+                    "<{0}{1}>{2}</{0}>",
+                    s.action.element(),
+                    path,
+                    escape_text(&s.expression.to_string())
+                )
+            })
+            .collect();
+
+        format!(
+            "<Query Id=\"{}\"{}>{}</Query>",
+            self.id,
+            path,
+            selectors.join("")
+        )
+    }
+}
+
+impl fmt::Display for QueryList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_xml())
+    }
+}
+
+impl FromStr for QueryList {
+    type Err = Error;
+
+    fn from_str(xml: &str) -> Result<Self> {
+        QueryList::parse(xml)
+    }
+}
+
+// quotes in expressions are left as is so they stay readable
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    escape_text(value).replace('"', "&quot;")
+}
+
+fn query_error(message: &str) -> Error {
+    Error {
+        kind: ErrorKind::Query,
+        message: format!("invalid query - {}", message),
+    }
+}
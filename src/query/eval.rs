@@ -0,0 +1,679 @@
+use super::{Action, Expr, Node, Operator, QueryList, Step};
+use crate::event::{Element, RawEvent};
+
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+
+impl QueryList {
+    // evaluates query against event the same way windows does. Event is
+    // selected when a Select of its channel matches and no Suppress of its
+    // channel matches, in any of the queries.
+    pub fn matches(&self, event: &RawEvent) -> bool {
+        let document = document(event);
+        let channel = &event.system.channel;
+
+        self.queries.iter().any(|query| {
+            let selectors = query.selectors.iter().filter(|s| match s.channel(query) {
+                Some(path) => path.eq_ignore_ascii_case(channel),
+                None => false,
+            });
+
+            let mut selected = false;
+            for selector in selectors {
+                if evaluate(&selector.expression, &document) {
+                    match selector.action {
+                        Action::Select => selected = true,
+                        Action::Suppress => return false,
+                    }
+                }
+            }
+            selected
+        })
+    }
+}
+
+impl Expr {
+    // evaluates xpath expression against event, e.g. *[System[(Level=2)]]
+    pub fn matches(&self, event: &RawEvent) -> bool {
+        evaluate(self, &document(event))
+    }
+}
+
+// builds System and EventData of document from typed event fields
+trait Build {
+    fn text<T: ToString>(self, text: T) -> Self;
+    fn attribute<T: ToString>(self, name: &str, value: Option<T>) -> Self;
+    fn child(self, child: Element) -> Self;
+}
+
+impl Build for Element {
+    fn text<T: ToString>(mut self, text: T) -> Self {
+        self.text = text.to_string();
+        self
+    }
+
+    fn attribute<T: ToString>(mut self, name: &str, value: Option<T>) -> Self {
+        if let Some(value) = value {
+            self.attributes.push((name.to_owned(), value.to_string()));
+        }
+        self
+    }
+
+    fn child(mut self, child: Element) -> Self {
+        self.children.push(child);
+        self
+    }
+}
+
+fn element(name: &str) -> Element {
+    Element {
+        name: name.to_owned(),
+        ..Default::default()
+    }
+}
+
+// Document is event xml as tree, xpath is evaluated on it. UserData is
+// borrowed from event and is the last child of Event.
+struct Document<'a> {
+    root: Element,
+    user_data: Option<&'a Element>,
+}
+
+impl<'a> Document<'a> {
+    fn children<'d>(&'d self, parent: &'d Element) -> impl Iterator<Item = &'d Element> {
+        let user_data = match self.root.children.first() {
+            Some(event) if std::ptr::eq(event, parent) => self.user_data,
+            _ => None,
+        };
+        parent.children.iter().chain(user_data)
+    }
+}
+
+// document node with Event as only child
+fn document(event: &RawEvent) -> Document<'_> {
+    let system = &event.system;
+    let execution = &system.execution;
+
+    let mut root = element("Event").child(
+        element("System")
+            .child(
+                element("Provider")
+                    .attribute("Name", system.provider.name.as_ref())
+                    .attribute("Guid", system.provider.guid.as_ref())
+                    .attribute(
+                        "EventSourceName",
+                        system.provider.event_source_name.as_ref(),
+                    ),
+            )
+            .child(
+                element("EventID")
+                    .attribute("Qualifiers", system.event_id.qualifiers)
+                    .text(system.event_id.id),
+            )
+            .child(element("Version").text(system.version))
+            .child(element("Level").text(system.level))
+            .child(element("Task").text(system.task))
+            .child(element("Opcode").text(system.opcode))
+            .child(element("Keywords").text(&system.keywords))
+            .child(
+                element("TimeCreated")
+                    .attribute("SystemTime", system.time_created.system_time.as_ref()),
+            )
+            .child(
+                element("EventRecordID").text(
+                    system
+                        .event_record_id
+                        .map(|id| id.to_string())
+                        .unwrap_or_default(),
+                ),
+            )
+            .child(
+                element("Correlation")
+                    .attribute("ActivityID", system.correlation.activity_id.as_ref())
+                    .attribute(
+                        "RelatedActivityID",
+                        system.correlation.related_activity_id.as_ref(),
+                    ),
+            )
+            .child(
+                element("Execution")
+                    .attribute("ProcessID", Some(execution.process_id))
+                    .attribute("ThreadID", Some(execution.thread_id))
+                    .attribute("ProcessorID", execution.processor_id)
+                    .attribute("SessionID", execution.session_id)
+                    .attribute("KernelTime", execution.kernel_time)
+                    .attribute("UserTime", execution.user_time)
+                    .attribute("ProcessorTime", execution.processor_time),
+            )
+            .child(element("Channel").text(&system.channel))
+            .child(element("Computer").text(&system.computer))
+            .child(element("Security").attribute("UserID", system.security.user_id.as_ref())),
+    );
+
+    if let Some(event_data) = &event.event_data {
+        let mut data_element = element("EventData");
+        for data in event_data.data.iter().flatten() {
+            data_element = data_element.child(
+                element("Data")
+                    .attribute("Name", data.name.as_ref())
+                    .text(data.value.clone().unwrap_or_default()),
+            );
+        }
+        if let Some(binary) = &event_data.binary {
+            data_element = data_element.child(element("Binary").text(binary));
+        }
+        root = root.child(data_element);
+    }
+
+    Document {
+        root: element("").child(root),
+        user_data: event.user_data.as_ref(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Item<'a> {
+    Element(&'a Element),
+    Attribute(&'a str),
+}
+
+impl<'a> Item<'a> {
+    fn value(&self) -> String {
+        match self {
+            Item::Element(e) => e.value(),
+            Item::Attribute(v) => v.to_string(),
+        }
+    }
+}
+
+enum Value<'a> {
+    Items(Vec<Item<'a>>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+impl<'a> Value<'a> {
+    fn boolean(&self) -> bool {
+        match self {
+            Value::Items(items) => !items.is_empty(),
+            Value::String(s) => !s.is_empty(),
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Boolean(b) => *b,
+        }
+    }
+
+    // values compared against each other, every item for node sets
+    fn strings(&self) -> Vec<String> {
+        match self {
+            Value::Items(items) => items.iter().map(|i| i.value()).collect(),
+            Value::String(s) => vec![s.clone()],
+            Value::Number(n) => vec![n.to_string()],
+            Value::Boolean(b) => vec![b.to_string()],
+        }
+    }
+}
+
+// context item and its position among selected siblings
+struct Context<'a> {
+    item: Item<'a>,
+    position: usize,
+    document: &'a Document<'a>,
+}
+
+fn evaluate(expr: &Expr, document: &Document) -> bool {
+    let context = Context {
+        item: Item::Element(&document.root),
+        position: 1,
+        document,
+    };
+    value(expr, &context).boolean()
+}
+
+fn value<'a>(expr: &Expr, context: &Context<'a>) -> Value<'a> {
+    match expr {
+        Expr::Path(steps) => Value::Items(select(steps, context.item, context.document)),
+        Expr::String(s) => Value::String(s.clone()),
+        Expr::Number(n) => Value::Number(*n as f64),
+        Expr::Group(e) => value(e, context),
+        Expr::Binary(lhs, Operator::And, rhs) => {
+            Value::Boolean(value(lhs, context).boolean() && value(rhs, context).boolean())
+        }
+        Expr::Binary(lhs, Operator::Or, rhs) => {
+            Value::Boolean(value(lhs, context).boolean() || value(rhs, context).boolean())
+        }
+        Expr::Binary(lhs, op, rhs) => {
+            Value::Boolean(compare(&value(lhs, context), *op, &value(rhs, context)))
+        }
+        Expr::Function(name, args) => function(name, args, context),
+    }
+}
+
+fn select<'a>(steps: &[Step], item: Item<'a>, document: &'a Document<'a>) -> Vec<Item<'a>> {
+    let mut items = vec![item];
+
+    for step in steps {
+        let mut next = Vec::new();
+
+        for item in items {
+            let element = match item {
+                Item::Element(e) => e,
+                Item::Attribute(_) => continue,
+            };
+
+            let selected: Vec<Item<'a>> = match &step.node {
+                Node::Any => document.children(element).map(Item::Element).collect(),
+                Node::Element(name) => document
+                    .children(element)
+                    .filter(|c| &c.name == name)
+                    .map(Item::Element)
+                    .collect(),
+                Node::Attribute(name) => element
+                    .attributes
+                    .iter()
+                    .filter(|(n, _)| n == name)
+                    .map(|(_, v)| Item::Attribute(v))
+                    .collect(),
+            };
+
+            next.extend(
+                step.predicates
+                    .iter()
+                    .fold(selected, |items, p| filter(items, p, document)),
+            );
+        }
+
+        items = next;
+    }
+
+    items
+}
+
+fn filter<'a>(items: Vec<Item<'a>>, predicate: &Expr, document: &'a Document<'a>) -> Vec<Item<'a>> {
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, item)| {
+            let context = Context {
+                item: *item,
+                position: i + 1,
+                document,
+            };
+            // number literal selects item at that position, [1], while
+            // windows takes band() result as true when it is not zero
+            match predicate {
+                Expr::Number(n) => *n == (i + 1) as u64,
+                p => value(p, &context).boolean(),
+            }
+        })
+        .map(|(_, item)| item)
+        .collect()
+}
+
+fn function<'a>(name: &str, args: &[Expr], context: &Context<'a>) -> Value<'a> {
+    match name {
+        "position" => Value::Number(context.position as f64),
+        // band(Keywords,0x8000000000000000)
+        "band" => {
+            let operands: Vec<Option<u64>> = args.iter().map(|a| integer(a, context)).collect();
+            match operands.as_slice() {
+                [Some(a), Some(b)] => Value::Number((a & b) as f64),
+                _ => Value::Number(f64::NAN),
+            }
+        }
+        // milliseconds since given time, future times are 0 like the age
+        // filter::Config compares, or milliseconds between two times
+        "timediff" => {
+            let times: Vec<Option<DateTime<Utc>>> = args
+                .iter()
+                .map(|a| value(a, context).strings().first().and_then(|t| time(t)))
+                .collect();
+            let diff = match times.as_slice() {
+                [Some(t)] => Some((Utc::now() - *t).num_milliseconds().max(0)),
+                [Some(a), Some(b)] => Some((*a - *b).num_milliseconds().abs()),
+                _ => None,
+            };
+            match diff {
+                Some(d) => Value::Number(d as f64),
+                None => Value::Number(f64::NAN),
+            }
+        }
+        _ => Value::Boolean(false),
+    }
+}
+
+// exact integer value, keywords don't fit in f64
+fn integer(expr: &Expr, context: &Context) -> Option<u64> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        e => value(e, context)
+            .strings()
+            .first()
+            .and_then(|s| parse_integer(s.trim())),
+    }
+}
+
+fn parse_integer(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u64>().ok(),
+    }
+}
+
+fn time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// compares text of event field with literal the same way query does
+pub(crate) fn compare_text(text: &str, op: Operator, literal: &Expr) -> bool {
+    let literal = match literal {
+        Expr::String(s) => Value::String(s.clone()),
+        Expr::Number(n) => Value::Number(*n as f64),
+        _ => return false,
+    };
+    compare(&Value::String(text.to_owned()), op, &literal)
+}
+
+// node sets match when any of their items matches
+fn compare(lhs: &Value, op: Operator, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Boolean(a), b) | (b, Value::Boolean(a))
+            if op == Operator::Eq || op == Operator::Ne =>
+        {
+            (*a == b.boolean()) == (op == Operator::Eq)
+        }
+        (Value::Number(a), b) => b.strings().iter().any(|s| match number(s) {
+            Some(b) => compare_ordering(a.partial_cmp(&b), op),
+            None => false,
+        }),
+        (a, Value::Number(b)) => a.strings().iter().any(|s| match number(s) {
+            Some(a) => compare_ordering(a.partial_cmp(b), op),
+            None => false,
+        }),
+        (a, b) => {
+            let b = b.strings();
+            a.strings().iter().any(|a| {
+                b.iter()
+                    .any(|b| compare_ordering(compare_strings(a, b), op))
+            })
+        }
+    }
+}
+
+fn number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    match parse_integer(s) {
+        Some(n) => Some(n as f64),
+        None => s.parse::<f64>().ok(),
+    }
+}
+
+// numbers and times are compared by value, other strings case insensitively
+fn compare_strings(a: &str, b: &str) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.partial_cmp(&b);
+    }
+    if let (Some(a), Some(b)) = (time(a), time(b)) {
+        return Some(a.cmp(&b));
+    }
+    Some(a.to_lowercase().cmp(&b.to_lowercase()))
+}
+
+fn compare_ordering(ordering: Option<Ordering>, op: Operator) -> bool {
+    match ordering {
+        Some(o) => match op {
+            Operator::Eq => o == Ordering::Equal,
+            Operator::Ne => o != Ordering::Equal,
+            Operator::Lt => o == Ordering::Less,
+            Operator::Le => o != Ordering::Greater,
+            Operator::Gt => o == Ordering::Greater,
+            Operator::Ge => o != Ordering::Less,
+            Operator::And | Operator::Or => false,
+        },
+        // NaN never compares, except for !=
+        None => op == Operator::Ne,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, SecondsFormat};
+    use std::convert::TryFrom;
+
+    // Security 4625 created an hour ago
+    fn event() -> RawEvent {
+        let created = (Utc::now() - Duration::hours(1)).to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let xml = format!(
+            r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System>
+    <Provider Name='Microsoft-Windows-Security-Auditing' Guid='{{54849625-5478-4994-a5ba-3e3b0328c30d}}'/>
+    <EventID>4625</EventID>
+    <Version>0</Version>
+    <Level>0</Level>
+    <Task>12544</Task>
+    <Opcode>0</Opcode>
+    <Keywords>0x8010000000000000</Keywords>
+    <TimeCreated SystemTime='{}'/>
+    <EventRecordID>1024</EventRecordID>
+    <Correlation/>
+    <Execution ProcessID='636' ThreadID='720'/>
+    <Channel>Security</Channel>
+    <Computer>DC01</Computer>
+    <Security/>
+  </System>
+  <EventData>
+    <Data Name='TargetUserName'>Alice</Data>
+    <Data Name='LogonType'>10</Data>
+    <Data Name='IpAddress'>10.0.0.7</Data>
+  </EventData>
+</Event>"#,
+            created
+        );
+        RawEvent::try_from(xml).unwrap()
+    }
+
+    fn matches(xpath: &str) -> bool {
+        Expr::parse(xpath).unwrap().matches(&event())
+    }
+
+    fn list_matches(xml: &str) -> bool {
+        QueryList::parse(xml).unwrap().matches(&event())
+    }
+
+    #[test]
+    fn paths_and_predicates() {
+        assert!(matches("*"));
+        assert!(matches("Event/System/EventRecordID"));
+        assert!(matches(
+            "*[System[Provider[@Name='Microsoft-Windows-Security-Auditing']]]"
+        ));
+        assert!(matches("*[System[EventID=4625 and (Level=0 or Level=4)]]"));
+        assert!(!matches("*[System[EventID=4625 and Level=2]]"));
+        assert!(!matches("*[UserData]"));
+        assert!(matches("*[EventData[Data[@Name='LogonType']=10]]"));
+        assert!(!matches("*[EventData[Data[@Name='LogonType']=3]]"));
+    }
+
+    #[test]
+    fn number_comparisons() {
+        assert!(matches("*[System[EventID >= 4624 and EventID <= 4634]]"));
+        assert!(matches("*[System[EventID > 4624]]"));
+        assert!(!matches("*[System[EventID < 4625]]"));
+        assert!(matches("*[System[EventID!=4624]]"));
+        assert!(matches("*[System[Keywords=0x8010000000000000]]"));
+        assert!(matches("*[System[Execution[@ProcessID > 600]]]"));
+        // text which isn't a number never compares
+        assert!(!matches("*[System[Computer=1]]"));
+        assert!(!matches("*[System[Computer > 1]]"));
+    }
+
+    #[test]
+    fn string_comparisons() {
+        assert!(matches(
+            "*[EventData[Data[@Name='TargetUserName']='alice']]"
+        ));
+        assert!(matches("*[EventData[Data[@Name='TargetUserName']!='bob']]"));
+        assert!(matches(
+            "*[EventData[Data[@Name='TargetUserName'] < 'bob']]"
+        ));
+        // any data item may match
+        assert!(matches("*[EventData[Data='10.0.0.7']]"));
+        assert!(matches("*[EventData[Data!='Alice']]"));
+        assert!(!matches("*[EventData[Data='carol']]"));
+        // numeric strings compare by value
+        assert!(matches("*[EventData[Data[@Name='LogonType'] > '9']]"));
+    }
+
+    #[test]
+    fn time_comparisons() {
+        let hour_ago = Utc::now() - Duration::hours(1);
+        let before = (hour_ago - Duration::minutes(1)).to_rfc3339_opts(SecondsFormat::Millis, true);
+        let after = (hour_ago + Duration::minutes(1)).to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        assert!(matches(&format!(
+            "*[System[TimeCreated[@SystemTime >= '{}' and @SystemTime <= '{}']]]",
+            before, after
+        )));
+        assert!(!matches(&format!(
+            "*[System[TimeCreated[@SystemTime >= '{}']]]",
+            after
+        )));
+    }
+
+    #[test]
+    fn functions() {
+        assert!(matches("*[System[band(Keywords,0x10000000000000)]]"));
+        assert!(matches("*[System[band(Keywords,0x8000000000000000)]]"));
+        assert!(!matches("*[System[band(Keywords,0x20000000000000)]]"));
+        assert!(matches(
+            "*[System[band(Keywords,4503599627370496)=4503599627370496]]"
+        ));
+
+        assert!(matches(
+            "*[System[TimeCreated[timediff(@SystemTime) <= 86400000]]]"
+        ));
+        assert!(matches(
+            "*[System[TimeCreated[timediff(@SystemTime) >= 3500000]]]"
+        ));
+        assert!(!matches(
+            "*[System[TimeCreated[timediff(@SystemTime) <= 60000]]]"
+        ));
+        assert!(matches(
+            "*[System[TimeCreated[timediff('2021-03-01T00:00:00Z','2021-03-01T00:00:01Z')=1000]]]"
+        ));
+        assert!(!matches("*[System[TimeCreated[timediff(@Missing) >= 0]]]"));
+
+        assert!(matches("*[EventData[Data[1][@Name='TargetUserName']]]"));
+        assert!(matches(
+            "*[EventData[Data[position()=2][@Name='LogonType']]]"
+        ));
+        assert!(!matches("*[EventData[Data[position() > 3]]]"));
+        assert!(matches("*[EventData[Data[position()=3]='10.0.0.7']]"));
+    }
+
+    #[test]
+    fn user_data() {
+        let xml = r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>
+  <System>
+    <Provider Name='Microsoft-Windows-Eventlog'/>
+    <EventID>1102</EventID>
+    <Version>0</Version>
+    <Level>4</Level>
+    <Task>104</Task>
+    <Opcode>0</Opcode>
+    <Keywords>0x4020000000000000</Keywords>
+    <TimeCreated SystemTime='2021-03-01T10:00:00.0000000Z'/>
+    <EventRecordID>7</EventRecordID>
+    <Correlation/>
+    <Execution ProcessID='1' ThreadID='2'/>
+    <Channel>Security</Channel>
+    <Computer>host</Computer>
+    <Security/>
+  </System>
+  <UserData>
+    <LogFileCleared xmlns='http://manifests.microsoft.com/win/2004/08/windows/eventlog'>
+      <SubjectUserName>bob</SubjectUserName>
+      <Rule Id='1'>a</Rule>
+      <Rule Id='2'>b</Rule>
+    </LogFileCleared>
+  </UserData>
+</Event>"#;
+        let event = RawEvent::try_from(xml.to_owned()).unwrap();
+        let matches = |xpath: &str| Expr::parse(xpath).unwrap().matches(&event);
+
+        assert!(matches("*[UserData/LogFileCleared[SubjectUserName='bob']]"));
+        assert!(matches("*[UserData/*/Rule[@Id=2]='b']"));
+        assert!(matches("Event/UserData/*/Rule[2][@Id=2]"));
+        assert!(!matches(
+            "*[UserData/LogFileCleared[SubjectUserName='alice']]"
+        ));
+        assert!(matches("*[System[EventID=1102]] and *[UserData]"));
+        assert!(!matches("*[EventData]"));
+        // UserData is only a child of Event
+        assert!(!matches("*/System/UserData"));
+    }
+
+    #[test]
+    fn select_and_suppress() {
+        // select of the event channel
+        assert!(list_matches(
+            r#"<QueryList><Query Id="0" Path="Security">
+                <Select>*[System[EventID=4625]]</Select>
+            </Query></QueryList>"#
+        ));
+
+        // matching suppress removes selected event
+        assert!(!list_matches(
+            r#"<QueryList><Query Id="0" Path="Security">
+                <Select>*</Select>
+                <Suppress>*[EventData[Data[@Name='LogonType']=10]]</Suppress>
+            </Query></QueryList>"#
+        ));
+
+        // suppress which doesn't match keeps it
+        assert!(list_matches(
+            r#"<QueryList><Query Id="0" Path="Security">
+                <Select>*</Select>
+                <Suppress>*[EventData[Data[@Name='LogonType']=3]]</Suppress>
+            </Query></QueryList>"#
+        ));
+
+        // selectors of other channels don't apply, channel is case insensitive
+        assert!(!list_matches(
+            r#"<QueryList><Query Id="0" Path="Security">
+                <Select Path="Application">*</Select>
+            </Query></QueryList>"#
+        ));
+        assert!(!list_matches(
+            r#"<QueryList><Query Id="0" Path="security">
+                <Select>*[System[EventID=4625]]</Select>
+                <Suppress Path="SECURITY">*</Suppress>
+            </Query></QueryList>"#
+        ));
+        assert!(list_matches(
+            r#"<QueryList><Query Id="0" Path="Security">
+                <Select>*</Select>
+                <Suppress Path="Application">*</Suppress>
+            </Query></QueryList>"#
+        ));
+
+        // suppress only applies to its own query
+        assert!(list_matches(
+            r#"<QueryList>
+                <Query Id="0" Path="Security">
+                    <Select>*[System[EventID=4624]]</Select>
+                </Query>
+                <Query Id="1" Path="Security">
+                    <Select>*[System[EventID=4625]]</Select>
+                </Query>
+                <Query Id="2" Path="Security">
+                    <Select>*</Select>
+                    <Suppress>*</Suppress>
+                </Query>
+            </QueryList>"#
+        ));
+    }
+}
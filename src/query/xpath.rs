@@ -0,0 +1,676 @@
+use super::query_error;
+use crate::error::Result;
+
+use std::fmt;
+
+// deepest nesting of parentheses, predicates and function arguments, parser
+// recurses on each level
+const MAX_DEPTH: usize = 64;
+
+// Expr is the subset of xpath windows accepts in event queries:
+// *[System[Provider[@Name='Service Control Manager'] and (Level=1 or Level=2)]]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    // location path, e.g. System/EventID or @Name
+    Path(Vec<Step>),
+    String(String),
+    Number(u64),
+    // function call, e.g. timediff(@SystemTime) or band(Keywords,8)
+    Function(String, Vec<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+    // expression in parentheses
+    Group(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub node: Node,
+    pub predicates: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    // *
+    Any,
+    Element(String),
+    Attribute(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operator {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Eq | Operator::Ne => 3,
+            _ => 4,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Or => " or ",
+            Operator::And => " and ",
+            Operator::Eq => "=",
+            Operator::Ne => "!=",
+            Operator::Lt => " < ",
+            Operator::Le => " <= ",
+            Operator::Gt => " > ",
+            Operator::Ge => " >= ",
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(xpath: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(xpath)?,
+            pos: 0,
+            end: xpath.len(),
+            depth: 0,
+        };
+
+        let expr = parser.expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(_) => Err(parser.unexpected()),
+        }
+    }
+
+    // path of element names, "*" is any element and names starting with @ are
+    // attributes
+    pub fn path(names: &[&str]) -> Self {
+        let steps = names
+            .iter()
+            .map(|name| Step {
+                node: match *name {
+                    "*" => Node::Any,
+                    n if n.starts_with('@') => Node::Attribute(n[1..].to_owned()),
+                    n => Node::Element(n.to_owned()),
+                },
+                predicates: Vec::new(),
+            })
+            .collect();
+
+        Expr::Path(steps)
+    }
+
+    // adds predicate to last step of path, other expressions are returned as is
+    pub fn with_predicate(mut self, predicate: Expr) -> Self {
+        if let Expr::Path(steps) = &mut self {
+            if let Some(step) = steps.last_mut() {
+                step.predicates.push(predicate);
+            }
+        }
+        self
+    }
+
+    pub fn binary(self, op: Operator, rhs: Expr) -> Self {
+        Expr::Binary(Box::new(self), op, Box::new(rhs))
+    }
+
+    pub fn group(self) -> Self {
+        Expr::Group(Box::new(self))
+    }
+
+    // joins expressions with operator, None when there are no expressions
+    pub fn join(op: Operator, exprs: Vec<Expr>) -> Option<Self> {
+        exprs.into_iter().fold(None, |acc, e| match acc {
+            Some(lhs) => Some(Expr::binary(lhs, op, e)),
+            None => Some(e),
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Path(steps) => {
+                for (i, step) in steps.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "/")?;
+                    }
+                    write!(f, "{}", step)?;
+                }
+                Ok(())
+            }
+            // xpath has no escapes, pick quote not used in value
+            Expr::String(s) if s.contains('\'') => write!(f, "\"{}\"", s),
+            Expr::String(s) => write!(f, "'{}'", s),
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Function(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                // operators are left associative, parentheses are added where
+                // tree built in code differs from parse order
+                write_operand(f, lhs, op.precedence())?;
+                write!(f, "{}", op.symbol())?;
+                write_operand(f, rhs, op.precedence() + 1)
+            }
+            Expr::Group(e) => write!(f, "({})", e),
+        }
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter, e: &Expr, precedence: u8) -> fmt::Result {
+    match e {
+        Expr::Binary(_, op, _) if op.precedence() < precedence => write!(f, "({})", e),
+        _ => write!(f, "{}", e),
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.node {
+            Node::Any => write!(f, "*")?,
+            Node::Element(name) => write!(f, "{}", name)?,
+            Node::Attribute(name) => write!(f, "@{}", name)?,
+        }
+        for predicate in &self.predicates {
+            write!(f, "[{}]", predicate)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    String(String),
+    Number(u64),
+    Operator(Operator),
+    Star,
+    At,
+    Slash,
+    Comma,
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{}", name),
+            Token::String(s) => write!(f, "'{}'", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Operator(op) => write!(f, "{}", op.symbol().trim()),
+            Token::Star => write!(f, "*"),
+            Token::At => write!(f, "@"),
+            Token::Slash => write!(f, "/"),
+            Token::Comma => write!(f, ","),
+            Token::OpenBracket => write!(f, "["),
+            Token::CloseBracket => write!(f, "]"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+        }
+    }
+}
+
+// tokens with their byte position in expression
+fn tokenize(xpath: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = xpath.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '*' => Token::Star,
+            '@' => Token::At,
+            '/' => Token::Slash,
+            ',' => Token::Comma,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '=' => Token::Operator(Operator::Eq),
+            '!' => match chars.next() {
+                Some((_, '=')) => Token::Operator(Operator::Ne),
+                _ => return Err(query_error(&format!("unexpected '!' at position {}", pos))),
+            },
+            '<' | '>' => {
+                let equal = chars.next_if(|&(_, c)| c == '=').is_some();
+                Token::Operator(match (c, equal) {
+                    ('<', false) => Operator::Lt,
+                    ('<', true) => Operator::Le,
+                    (_, false) => Operator::Gt,
+                    (_, true) => Operator::Ge,
+                })
+            }
+            '\'' | '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, q)) if q == c => break,
+                        Some((_, v)) => value.push(v),
+                        None => {
+                            return Err(query_error(&format!(
+                                "unterminated string at position {}",
+                                pos
+                            )))
+                        }
+                    }
+                }
+                Token::String(value)
+            }
+            c if c.is_ascii_digit() => {
+                let mut digits = c.to_string();
+                while let Some((_, d)) = chars.next_if(|&(_, d)| d.is_ascii_alphanumeric()) {
+                    digits.push(d);
+                }
+
+                let number = match digits.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => digits.parse::<u64>(),
+                };
+                match number {
+                    Ok(n) => Token::Number(n),
+                    Err(_) => {
+                        return Err(query_error(&format!(
+                            "invalid number '{}' at position {}",
+                            digits, pos
+                        )))
+                    }
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some((_, n)) = chars.next_if(|&(_, n)| {
+                    n.is_alphanumeric() || n == '_' || n == '-' || n == '.' || n == ':'
+                }) {
+                    name.push(n);
+                }
+                Token::Name(name)
+            }
+            c => {
+                return Err(query_error(&format!(
+                    "unexpected '{}' at position {}",
+                    c, pos
+                )))
+            }
+        };
+
+        tokens.push((pos, token));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // length of expression, position reported at the end
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn unexpected(&self) -> crate::error::Error {
+        match self.tokens.get(self.pos) {
+            Some((pos, token)) => {
+                query_error(&format!("unexpected '{}' at position {}", token, pos))
+            }
+            None => query_error(&format!(
+                "unexpected end of expression at position {}",
+                self.end
+            )),
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<()> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    // binary operator at current position, and/or are names in xpath
+    fn operator(&self) -> Option<Operator> {
+        match self.peek() {
+            Some(Token::Operator(op)) => Some(*op),
+            Some(Token::Name(name)) if name == "and" => Some(Operator::And),
+            Some(Token::Name(name)) if name == "or" => Some(Operator::Or),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, precedence: u8) -> Result<Expr> {
+        if self.depth >= MAX_DEPTH {
+            let pos = self.tokens.get(self.pos).map_or(self.end, |(pos, _)| *pos);
+            return Err(query_error(&format!(
+                "expression nested too deeply at position {}",
+                pos
+            )));
+        }
+
+        self.depth += 1;
+        let expr = self.binary(precedence);
+        self.depth -= 1;
+        expr
+    }
+
+    fn binary(&mut self, precedence: u8) -> Result<Expr> {
+        let mut lhs = self.primary()?;
+
+        while let Some(op) = self.operator() {
+            if op.precedence() < precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(op.precedence() + 1)?;
+            lhs = lhs.binary(op, rhs);
+        }
+
+        Ok(lhs)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let next_is_paren = matches!(self.tokens.get(self.pos + 1), Some((_, Token::OpenParen)));
+
+        match self.peek().cloned() {
+            Some(Token::OpenParen) => {
+                self.pos += 1;
+                let e = self.expr(0)?;
+                self.expect(Token::CloseParen)?;
+                Ok(e.group())
+            }
+            Some(Token::String(s)) => {
+                self.pos += 1;
+                Ok(Expr::String(s))
+            }
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(Expr::Number(n))
+            }
+            Some(Token::Name(name)) if next_is_paren => self.function(name),
+            Some(Token::Name(_)) | Some(Token::Star) | Some(Token::At) => self.location(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn function(&mut self, name: String) -> Result<Expr> {
+        let pos = self.tokens[self.pos].0;
+        self.pos += 1;
+        self.expect(Token::OpenParen)?;
+
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::CloseParen) {
+            loop {
+                args.push(self.expr(0)?);
+                if self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::CloseParen)?;
+
+        // only functions windows supports in event queries
+        let arity = match name.as_str() {
+            "band" => 2..=2,
+            "timediff" => 1..=2,
+            "position" => 0..=0,
+            _ => {
+                return Err(query_error(&format!(
+                    "unsupported function '{}' at position {}",
+                    name, pos
+                )))
+            }
+        };
+        if !arity.contains(&args.len()) {
+            return Err(query_error(&format!(
+                "wrong number of arguments to '{}' at position {}",
+                name, pos
+            )));
+        }
+
+        Ok(Expr::Function(name, args))
+    }
+
+    fn location(&mut self) -> Result<Expr> {
+        let mut steps = Vec::new();
+
+        loop {
+            let node = match self.next() {
+                Some(Token::Star) => Node::Any,
+                Some(Token::Name(name)) => Node::Element(name),
+                Some(Token::At) => match self.next() {
+                    Some(Token::Name(name)) => Node::Attribute(name),
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.unexpected());
+                    }
+                },
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected());
+                }
+            };
+
+            let mut predicates = Vec::new();
+            while self.peek() == Some(&Token::OpenBracket) {
+                self.pos += 1;
+                predicates.push(self.expr(0)?);
+                self.expect(Token::CloseBracket)?;
+            }
+
+            steps.push(Step { node, predicates });
+
+            if self.peek() == Some(&Token::Slash) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        Ok(Expr::Path(steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(n: &str) -> Expr {
+        Expr::path(&[n])
+    }
+
+    fn error(xpath: &str) -> String {
+        Expr::parse(xpath).unwrap_err().message
+    }
+
+    #[test]
+    fn round_trip() {
+        let queries = [
+            "*",
+            "*[System[Provider[@Name='Service Control Manager'] and (Level=1 or Level=2)]]",
+            "*[System[(EventID >= 4624 and EventID <= 4634) or EventID=4688]]",
+            "*[System[band(Keywords,8796093022208)]]",
+            "*[System[TimeCreated[timediff(@SystemTime) <= 86400000]]]",
+            "*[System[TimeCreated[@SystemTime >= '2021-03-01T00:00:00.000Z']]]",
+            "*[EventData[Data[@Name='TargetUserName']!=\"bob's\"]]",
+            "Event/System[EventID > 1 and EventID < 9][position()=1]",
+        ];
+
+        for query in queries.iter() {
+            let expr = Expr::parse(query).unwrap();
+            assert_eq!(&expr.to_string(), query);
+        }
+    }
+
+    #[test]
+    fn parses_tokens() {
+        let expr = Expr::parse(" *[ System / EventID = 0x10 ] ").unwrap();
+        assert_eq!(expr.to_string(), "*[System/EventID=16]");
+
+        let expr = Expr::parse("a or b and c").unwrap();
+        assert_eq!(
+            expr,
+            name("a").binary(Operator::Or, name("b").binary(Operator::And, name("c")))
+        );
+
+        // same precedence is left associative
+        let expr = Expr::parse("a < b <= c").unwrap();
+        assert_eq!(
+            expr,
+            name("a")
+                .binary(Operator::Lt, name("b"))
+                .binary(Operator::Le, name("c"))
+        );
+    }
+
+    #[test]
+    fn parenthesises_operands() {
+        let a_or_b = name("a").binary(Operator::Or, name("b"));
+        let b_and_c = name("b").binary(Operator::And, name("c"));
+
+        let cases = vec![
+            (
+                a_or_b.clone().binary(Operator::And, name("c")),
+                "(a or b) and c",
+            ),
+            (
+                name("a").binary(Operator::Or, b_and_c.clone()),
+                "a or b and c",
+            ),
+            (
+                a_or_b.clone().binary(Operator::Or, name("c")),
+                "a or b or c",
+            ),
+            (
+                name("c").binary(Operator::Or, a_or_b.clone()),
+                "c or (a or b)",
+            ),
+            (name("c").binary(Operator::And, b_and_c), "c and (b and c)"),
+            (
+                a_or_b.clone().group().binary(Operator::And, name("c")),
+                "(a or b) and c",
+            ),
+            (
+                name("a")
+                    .binary(Operator::Eq, Expr::Number(1))
+                    .binary(Operator::Eq, name("b")),
+                "a=1=b",
+            ),
+            (
+                name("a").binary(Operator::Eq, name("b").binary(Operator::Lt, name("c"))),
+                "a=b < c",
+            ),
+            (
+                name("a").binary(Operator::Lt, name("b").binary(Operator::Eq, name("c"))),
+                "a < (b=c)",
+            ),
+        ];
+
+        for (expr, expected) in cases {
+            assert_eq!(expr.to_string(), expected);
+            // written expression parses back to the same tree
+            let parsed = Expr::parse(expected).unwrap();
+            assert_eq!(parsed.to_string(), expected);
+        }
+
+        assert_eq!(
+            Expr::parse("c or (a or b)").unwrap(),
+            name("c").binary(Operator::Or, a_or_b.group())
+        );
+    }
+
+    #[test]
+    fn string_quotes() {
+        assert_eq!(Expr::String("bob".into()).to_string(), "'bob'");
+        assert_eq!(Expr::String("bob's".into()).to_string(), "\"bob's\"");
+        assert_eq!(
+            Expr::String("say \"hi\"".into()).to_string(),
+            "'say \"hi\"'"
+        );
+
+        let expr = Expr::parse("\"bob's\"").unwrap();
+        assert_eq!(expr, Expr::String("bob's".into()));
+    }
+
+    #[test]
+    fn error_positions() {
+        let cases = [
+            ("*[System[Level=1 and]]", "unexpected ']' at position 20"),
+            (
+                "*[System[Level=1]",
+                "unexpected end of expression at position 17",
+            ),
+            ("*[System[Level=1]] x", "unexpected 'x' at position 19"),
+            ("*[Data='abc]", "unterminated string at position 7"),
+            ("*[EventID=12ab]", "invalid number '12ab' at position 10"),
+            ("*[EventID ! 1]", "unexpected '!' at position 10"),
+            ("*[EventID=#]", "unexpected '#' at position 10"),
+            ("*[@=1]", "unexpected '=' at position 3"),
+            (
+                "*[count(Data)=1]",
+                "unsupported function 'count' at position 2",
+            ),
+            (
+                "*[band(Keywords)]",
+                "wrong number of arguments to 'band' at position 2",
+            ),
+            ("", "unexpected end of expression at position 0"),
+        ];
+
+        for (xpath, message) in cases.iter() {
+            assert_eq!(
+                &error(xpath),
+                &format!("invalid query - {}", message),
+                "{}",
+                xpath
+            );
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert!(Expr::parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(
+            error(&nested(MAX_DEPTH)),
+            format!(
+                "invalid query - expression nested too deeply at position {}",
+                MAX_DEPTH
+            )
+        );
+        assert!(error(&nested(100_000)).contains("nested too deeply"));
+
+        let predicates = format!("{}{}", "*[".repeat(100_000), "]".repeat(100_000));
+        assert!(error(&predicates).contains("nested too deeply"));
+    }
+}
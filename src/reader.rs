@@ -1,5 +1,8 @@
+use crate::bookmark::Bookmark;
 use crate::error::{Error, ErrorKind, Result};
-use crate::event::{Event, RawEvent, WinLogEvent};
+pub use crate::event::Output;
+use crate::event::WinLogEvent;
+use crate::source::EventSource;
 
 use bindings::{
     Windows::Win32::System::EventLog::{
@@ -7,14 +10,14 @@ use bindings::{
         EvtRender, EvtSubscribe, EvtUpdateBookmark,
     },
     Windows::Win32::System::SystemServices::{HANDLE, PWSTR},
-    Windows::Win32::System::Threading::CreateEventW,
+    Windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject},
     Windows::Win32::System::WindowsProgramming::CloseHandle,
 };
 
 use core::ffi::c_void;
 use quick_xml::{events::Event as QuickXmlEvent, Reader as QuickXmlReader};
-use std::convert::TryInto;
 use std::io::Error as IoError;
+use std::time::Duration;
 
 const DEFAULT_QUERY: &str = r#"
 <QueryList>
@@ -39,18 +42,10 @@ const EVT_RENDER_FLAG_BOOKMARK: u32 = 2;
 pub struct Config {
     pub read_oldest: bool,
     pub query: String,
-    pub bookmark: Option<String>,
+    pub bookmark: Option<Bookmark>,
     pub output: Output,
 }
 
-#[derive(Debug)]
-pub enum Output {
-    Xml,
-    Raw,
-    Parsed,
-    Json,
-}
-
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -79,8 +74,8 @@ impl Reader {
 
         let mut bookmark_handle: isize = 0;
 
-        if let Some(xml) = config.bookmark {
-            bookmark_handle = unsafe { EvtCreateBookmark(xml) };
+        if let Some(bookmark) = config.bookmark.filter(|b| !b.is_empty()) {
+            bookmark_handle = unsafe { EvtCreateBookmark(bookmark.to_xml()) };
         };
 
         if bookmark_handle != 0 {
@@ -162,35 +157,88 @@ impl Reader {
             EvtClose(event.unwrap());
         }
 
-        match self.output {
-            Output::Xml => Ok(WinLogEvent::Xml(xml)),
+        WinLogEvent::from_xml(xml, self.output)
+    }
+
+    // pulls up to max events with one EvtNext call. Events which fail to
+    // render are returned as errors in their place, bookmark is updated once
+    // with the last event before the first failure so failed events are read
+    // again after restart.
+    pub fn next_batch(&self, max: usize) -> Result<Vec<Result<WinLogEvent>>> {
+        let handles = next_events(&self.subscription_handle, max)?;
+
+        if handles.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::NoMoreLogs,
+                message: "".to_owned(),
+            });
+        }
+
+        let mut events: Vec<Result<WinLogEvent>> = handles
+            .iter()
+            .map(|h| format_event(h).and_then(|xml| WinLogEvent::from_xml(xml, self.output)))
+            .collect();
 
-            Output::Raw => Ok(WinLogEvent::Raw(xml.try_into()?)),
+        let rendered = events.iter().take_while(|e| e.is_ok()).count();
+        let bookmarked = match rendered {
+            0 => Ok(()),
+            n => update_bookmark(&handles[n - 1], &self.bookmark_handle),
+        };
 
-            Output::Parsed => Ok(WinLogEvent::Parsed(
-                TryInto::<RawEvent>::try_into(xml)?.into(),
-            )),
+        for handle in handles {
+            unsafe {
+                EvtClose(handle);
+            }
+        }
 
-            Output::Json => {
-                let event: Event = TryInto::<RawEvent>::try_into(xml)?.into();
-                match serde_json::to_string(&event) {
-                    Ok(json) => Ok(WinLogEvent::Json(json)),
-                    Err(err) => Err(Error {
-                        kind: ErrorKind::Event,
-                        message: err.to_string(),
-                    }),
+        // events are already taken from subscription, bookmark failure is
+        // reported after them
+        if let Err(err) = bookmarked {
+            events.push(Err(err));
+        }
+
+        Ok(events)
+    }
+}
+
+impl EventSource for Reader {
+    fn next_event(&mut self) -> Result<WinLogEvent> {
+        self.next()
+    }
+
+    fn bookmark(&self) -> Result<Bookmark> {
+        Bookmark::parse(&self.get_bookmark()?)
+    }
+
+    fn next_batch(&mut self, max: usize) -> Result<Vec<Result<WinLogEvent>>> {
+        Reader::next_batch(self, max)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.close_handles();
+        Ok(())
+    }
+
+    // signal is set by subscription when new events arrive
+    fn wait(&mut self, timeout: Duration) {
+        match self.signal {
+            Some(s) if !s.is_null() && !s.is_invalid() => {
+                let ms = timeout.as_millis().min(u32::MAX as u128) as u32;
+                unsafe {
+                    WaitForSingleObject(s, ms);
                 }
             }
+            _ => std::thread::sleep(timeout),
         }
     }
 }
 
-impl Drop for Reader {
-    fn drop(&mut self) {
-        if let Some(s) = self.signal {
+impl Reader {
+    fn close_handles(&mut self) {
+        if let Some(s) = self.signal.take() {
             if !s.is_null() && !s.is_invalid() {
                 unsafe {
-                    CloseHandle(self.signal);
+                    CloseHandle(s);
                 }
             }
         }
@@ -199,20 +247,35 @@ impl Drop for Reader {
             unsafe {
                 EvtClose(self.subscription_handle);
             }
+            self.subscription_handle = 0;
         }
         if self.bookmark_handle != 0 {
             unsafe { EvtClose(self.bookmark_handle) };
+            self.bookmark_handle = 0;
         }
     }
 }
 
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.close_handles();
+    }
+}
+
 fn process_event(event: &isize, bookmark_handle: &isize) -> Result<String> {
-    // update bookmark
+    update_bookmark(event, bookmark_handle)?;
+    format_event(event)
+}
+
+fn update_bookmark(event: &isize, bookmark_handle: &isize) -> Result<()> {
     if !unsafe { EvtUpdateBookmark(*bookmark_handle, *event).as_bool() } {
         let err = IoError::last_os_error();
         return Err(Error::event("unable to update bookmark", err));
     }
+    Ok(())
+}
 
+fn format_event(event: &isize) -> Result<String> {
     let provider = match render_event(event, EVT_RENDER_FLAG_EVENT_XML) {
         Ok(xml) => parse_provider_name(&xml),
         Err(_err) => None,
@@ -223,13 +286,17 @@ fn process_event(event: &isize, bookmark_handle: &isize) -> Result<String> {
 }
 
 fn next_event(subscription_handle: &isize) -> Result<Option<isize>> {
+    Ok(next_events(subscription_handle, 1)?.pop())
+}
+
+fn next_events(subscription_handle: &isize, max: usize) -> Result<Vec<isize>> {
     let mut event_count: u32 = 0;
-    let mut events: [isize; 1] = [0; 1];
+    let mut events: Vec<isize> = vec![0; max.max(1)];
 
     if unsafe {
         EvtNext(
             *subscription_handle,
-            1,
+            events.len() as u32,
             events.as_mut_ptr(),
             500, // 0.5sec
             0,
@@ -237,12 +304,13 @@ fn next_event(subscription_handle: &isize) -> Result<Option<isize>> {
         )
         .as_bool()
     } {
-        return Ok(Some(events[0]));
+        events.truncate(event_count as usize);
+        return Ok(events);
     }
 
     match IoError::last_os_error().raw_os_error() {
         // (1460) ERROR_TIMEOUT | (259) ERROR_NO_MORE_ITEMS | (4317) ERROR_INVALID_OPERATION
-        None | Some(1460) | Some(259) | Some(4317) => return Ok(None),
+        None | Some(1460) | Some(259) | Some(4317) => return Ok(Vec::new()),
 
         Some(e) => {
             return Err(Error::subscription(
@@ -0,0 +1,570 @@
+use crate::bookmark::Bookmark;
+use crate::checkpoint::{BookmarkStore, Checkpointer, Policy};
+use crate::error::{Error, ErrorKind, Result};
+use crate::event::{Event, RawEvent, WinLogEvent};
+
+use quick_xml::{events::Event as QuickXmlEvent, Reader as QuickXmlReader};
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+// EventSource is implemented by every event reader (live subscription, evtx
+// file, in memory events) so consumers don't depend on a specific reader.
+pub trait EventSource {
+    // next event, NoMoreLogs is returned when there are no events to pull
+    fn next_event(&mut self) -> Result<WinLogEvent>;
+
+    // bookmark of the last returned event
+    fn bookmark(&self) -> Result<Bookmark>;
+
+    // release resources, no events are returned after close
+    fn close(&mut self) -> Result<()>;
+
+    // up to max events, NoMoreLogs is returned when there are no events to
+    // pull. Failures are returned in place of their events so events read
+    // before them are never lost, batch ends after a failure which isn't an
+    // event error.
+    fn next_batch(&mut self, max: usize) -> Result<Vec<Result<WinLogEvent>>> {
+        let mut events = Vec::new();
+        while events.len() < max.max(1) {
+            match self.next_event() {
+                Ok(event) => events.push(Ok(event)),
+                Err(err) if err.kind == ErrorKind::Event => events.push(Err(err)),
+                Err(err) if events.is_empty() => return Err(err),
+                Err(err) if err.kind == ErrorKind::NoMoreLogs => break,
+                Err(err) => {
+                    events.push(Err(err));
+                    break;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    // blocks until new events may be available or timeout elapsed
+    fn wait(&mut self, timeout: Duration) {
+        thread::sleep(timeout)
+    }
+
+    // iterator over events of the source
+    fn events(self, mode: Mode) -> Events<Self>
+    where
+        Self: Sized,
+    {
+        Events::new(self, mode)
+    }
+
+    // saves bookmark of the source to store as configured by policy
+    fn checkpointed<B: BookmarkStore>(self, store: B, policy: Policy) -> Checkpointer<Self, B>
+    where
+        Self: Sized,
+    {
+        Checkpointer::new(self, store, policy)
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for &mut S {
+    fn next_event(&mut self) -> Result<WinLogEvent> {
+        (**self).next_event()
+    }
+
+    fn bookmark(&self) -> Result<Bookmark> {
+        (**self).bookmark()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        (**self).close()
+    }
+
+    fn next_batch(&mut self, max: usize) -> Result<Vec<Result<WinLogEvent>>> {
+        (**self).next_batch(max)
+    }
+
+    fn wait(&mut self, timeout: Duration) {
+        (**self).wait(timeout)
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for Box<S> {
+    fn next_event(&mut self) -> Result<WinLogEvent> {
+        (**self).next_event()
+    }
+
+    fn bookmark(&self) -> Result<Bookmark> {
+        (**self).bookmark()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        (**self).close()
+    }
+
+    fn next_batch(&mut self, max: usize) -> Result<Vec<Result<WinLogEvent>>> {
+        (**self).next_batch(max)
+    }
+
+    fn wait(&mut self, timeout: Duration) {
+        (**self).wait(timeout)
+    }
+}
+
+// Mode decides what iterator does when source has no more events
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // stop once existing events are read
+    Drain,
+    // wait for new events, checking source again after given interval
+    Follow(Duration),
+}
+
+// Events iterates over events of a source. Event errors are returned and
+// iteration continues, iteration stops after any other error.
+pub struct Events<S> {
+    source: S,
+    mode: Mode,
+    done: bool,
+}
+
+impl<S: EventSource> Events<S> {
+    pub fn new(source: S, mode: Mode) -> Self {
+        Self {
+            source,
+            mode,
+            done: false,
+        }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: EventSource> Iterator for Events<S> {
+    type Item = Result<WinLogEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.source.next_event() {
+                Ok(event) => return Some(Ok(event)),
+                Err(err) => match (err.kind, self.mode) {
+                    (ErrorKind::NoMoreLogs, Mode::Drain) => self.done = true,
+                    (ErrorKind::NoMoreLogs, Mode::Follow(interval)) => self.source.wait(interval),
+                    (ErrorKind::Event, _) => return Some(Err(err)),
+                    _ => {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+// MemorySource returns given events in order, useful for tests and replaying
+// events collected earlier.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    events: VecDeque<WinLogEvent>,
+    bookmark: Bookmark,
+}
+
+impl MemorySource {
+    pub fn new(events: Vec<WinLogEvent>) -> Self {
+        Self {
+            events: events.into(),
+            bookmark: Bookmark::default(),
+        }
+    }
+
+    pub fn push(&mut self, event: WinLogEvent) {
+        self.events.push_back(event);
+    }
+}
+
+impl EventSource for MemorySource {
+    fn next_event(&mut self) -> Result<WinLogEvent> {
+        match self.events.pop_front() {
+            Some(event) => {
+                if let Some((channel, record_id)) = event_position(&event) {
+                    self.bookmark.set(&channel, record_id);
+                }
+                Ok(event)
+            }
+            None => Err(Error {
+                kind: ErrorKind::NoMoreLogs,
+                message: "".to_owned(),
+            }),
+        }
+    }
+
+    fn bookmark(&self) -> Result<Bookmark> {
+        Ok(self.bookmark.clone())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.events.clear();
+        Ok(())
+    }
+}
+
+// channel and record id of event in any output format
+pub(crate) fn event_position(event: &WinLogEvent) -> Option<(String, u64)> {
+    match event {
+        WinLogEvent::Xml(xml) => xml_position(xml),
+        WinLogEvent::Raw(e) => raw_position(e),
+        WinLogEvent::Parsed(e) => Some((e.channel.clone(), e.record_id)),
+        WinLogEvent::Json(json) => match serde_json::from_str::<Event>(json) {
+            Ok(e) => Some((e.channel, e.record_id)),
+            Err(_) => None,
+        },
+    }
+}
+
+fn raw_position(event: &RawEvent) -> Option<(String, u64)> {
+    event
+        .system
+        .event_record_id
+        .map(|id| (event.system.channel.clone(), id))
+}
+
+// reads Channel and EventRecordID without parsing whole event
+pub(crate) fn xml_position(xml: &str) -> Option<(String, u64)> {
+    let mut reader = QuickXmlReader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut element = Vec::new();
+    let mut channel = None;
+    let mut record_id = None;
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(QuickXmlEvent::Start(ref e)) => element = e.name().to_vec(),
+            Ok(QuickXmlEvent::End(_)) => element.clear(),
+            Ok(QuickXmlEvent::Text(ref t)) => match element.as_slice() {
+                b"Channel" => channel = t.unescape_and_decode(&reader).ok(),
+                b"EventRecordID" => {
+                    record_id = t
+                        .unescape_and_decode(&reader)
+                        .ok()
+                        .and_then(|id| id.parse::<u64>().ok())
+                }
+                _ => (),
+            },
+            Ok(QuickXmlEvent::Eof) | Err(_) => break,
+            _ => (),
+        }
+
+        if channel.is_some() && record_id.is_some() {
+            break;
+        }
+        buf.clear();
+    }
+
+    match (channel, record_id) {
+        (Some(c), Some(id)) => Some((c, id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    // memory source failing with given error kind on given events
+    struct Failing {
+        inner: MemorySource,
+        read: usize,
+        fail: Vec<(usize, ErrorKind)>,
+    }
+
+    impl EventSource for Failing {
+        fn next_event(&mut self) -> Result<WinLogEvent> {
+            self.read += 1;
+            if let Some((_, kind)) = self.fail.iter().find(|(n, _)| *n == self.read) {
+                return Err(Error {
+                    kind: *kind,
+                    message: format!("bad record {}", self.read),
+                });
+            }
+            self.inner.next_event()
+        }
+
+        fn bookmark(&self) -> Result<Bookmark> {
+            self.inner.bookmark()
+        }
+
+        fn close(&mut self) -> Result<()> {
+            self.inner.close()
+        }
+    }
+
+    fn failing(count: usize, fail: Vec<(usize, ErrorKind)>) -> Failing {
+        let events = (1..=count)
+            .map(|i| WinLogEvent::Xml(format!("e{}", i)))
+            .collect();
+        Failing {
+            inner: MemorySource::new(events),
+            read: 0,
+            fail,
+        }
+    }
+
+    fn summary(batch: Vec<Result<WinLogEvent>>) -> Vec<String> {
+        batch
+            .into_iter()
+            .map(|e| match e {
+                Ok(WinLogEvent::Xml(xml)) => xml,
+                Ok(e) => format!("{:?}", e),
+                Err(err) => err.message,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_keeps_events_around_event_error() {
+        let mut source = failing(4, vec![(3, ErrorKind::Event)]);
+
+        let batch = source.next_batch(10).unwrap();
+        assert_eq!(summary(batch), vec!["e1", "e2", "bad record 3", "e3", "e4"]);
+
+        let err = source.next_batch(10).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NoMoreLogs);
+    }
+
+    #[test]
+    fn batch_ends_after_source_error() {
+        let mut source = failing(4, vec![(3, ErrorKind::Subscription)]);
+
+        let batch = source.next_batch(10).unwrap();
+        assert_eq!(summary(batch), vec!["e1", "e2", "bad record 3"]);
+
+        let batch = source.next_batch(10).unwrap();
+        assert_eq!(summary(batch), vec!["e3", "e4"]);
+    }
+
+    #[test]
+    fn batch_error_without_events() {
+        let mut source = failing(1, vec![(1, ErrorKind::Subscription)]);
+        let err = source.next_batch(10).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Subscription);
+
+        let batch = source.next_batch(1).unwrap();
+        assert_eq!(summary(batch), vec!["e1"]);
+    }
+
+    fn xml(channel: &str, record_id: u64) -> String {
+        format!(
+            "<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'><System>\
+             <Provider Name='p'/><EventID>1</EventID><Version>0</Version><Level>0</Level>\
+             <Task>0</Task><Opcode>0</Opcode><Keywords>0x0</Keywords><TimeCreated/>\
+             <EventRecordID>{}</EventRecordID><Correlation/>\
+             <Execution ProcessID='1' ThreadID='1'/><Channel>{}</Channel>\
+             <Computer>c</Computer><Security/></System></Event>",
+            record_id, channel
+        )
+    }
+
+    #[test]
+    fn position_from_xml() {
+        assert_eq!(
+            xml_position(&xml("Security", 42)),
+            Some(("Security".to_owned(), 42))
+        );
+        assert_eq!(
+            xml_position(&xml("Tom&apos;s &amp; Co", 7)),
+            Some(("Tom's & Co".to_owned(), 7))
+        );
+        assert_eq!(
+            xml_position(&xml("Security", 0).replace(">0<", ">x<")),
+            None
+        );
+        assert_eq!(
+            xml_position("<Event><System><Channel>Security</Channel></System></Event>"),
+            None
+        );
+        assert_eq!(xml_position("not xml"), None);
+        // Channel of EventData isn't the event channel
+        assert_eq!(
+            xml_position(&xml("Security", 3).replace(
+                "</System>",
+                "</System><EventData><Data Name='Channel'>x</Data></EventData>"
+            )),
+            Some(("Security".to_owned(), 3))
+        );
+    }
+
+    #[test]
+    fn position_from_events() {
+        let raw = RawEvent::try_from(xml("System", 5)).unwrap();
+        let parsed = Event {
+            channel: "Application".to_owned(),
+            record_id: 6,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&parsed).unwrap();
+
+        assert_eq!(
+            event_position(&WinLogEvent::Xml(xml("Security", 4))),
+            Some(("Security".to_owned(), 4))
+        );
+        assert_eq!(
+            event_position(&WinLogEvent::Raw(raw)),
+            Some(("System".to_owned(), 5))
+        );
+        assert_eq!(
+            event_position(&WinLogEvent::Parsed(parsed)),
+            Some(("Application".to_owned(), 6))
+        );
+        assert_eq!(
+            event_position(&WinLogEvent::Json(json)),
+            Some(("Application".to_owned(), 6))
+        );
+        assert_eq!(event_position(&WinLogEvent::Json("{".to_owned())), None);
+        assert_eq!(event_position(&WinLogEvent::Xml("e1".to_owned())), None);
+    }
+
+    #[test]
+    fn memory_source_tracks_bookmark() {
+        let mut source = MemorySource::new(vec![
+            WinLogEvent::Xml(xml("Security", 10)),
+            WinLogEvent::Xml("no position".to_owned()),
+            WinLogEvent::Raw(RawEvent::try_from(xml("System", 3)).unwrap()),
+        ]);
+        assert!(source.bookmark().unwrap().is_empty());
+
+        source.next_event().unwrap();
+        assert_eq!(source.bookmark().unwrap().record_id("Security"), Some(10));
+
+        // event without position leaves bookmark as it is
+        source.next_event().unwrap();
+        let bookmark = source.bookmark().unwrap();
+        assert_eq!(bookmark.current(), Some("Security"));
+
+        source.next_event().unwrap();
+        let bookmark = source.bookmark().unwrap();
+        assert_eq!(bookmark.record_id("Security"), Some(10));
+        assert_eq!(bookmark.record_id("System"), Some(3));
+        assert_eq!(bookmark.current(), Some("System"));
+
+        assert_eq!(source.next_event().unwrap_err().kind, ErrorKind::NoMoreLogs);
+        source.push(WinLogEvent::Xml(xml("Security", 11)));
+        source.next_event().unwrap();
+        assert_eq!(source.bookmark().unwrap().record_id("Security"), Some(11));
+    }
+
+    #[test]
+    fn memory_source_close() {
+        let mut source = MemorySource::new(vec![
+            WinLogEvent::Xml(xml("Security", 1)),
+            WinLogEvent::Xml(xml("Security", 2)),
+        ]);
+        source.next_event().unwrap();
+        source.close().unwrap();
+
+        assert_eq!(source.next_event().unwrap_err().kind, ErrorKind::NoMoreLogs);
+        // bookmark still points at the last returned event
+        assert_eq!(source.bookmark().unwrap().record_id("Security"), Some(1));
+    }
+
+    // memory source which gets the next events each time it is waited on
+    struct Growing {
+        inner: MemorySource,
+        later: VecDeque<Vec<WinLogEvent>>,
+        waits: Vec<Duration>,
+    }
+
+    impl EventSource for Growing {
+        fn next_event(&mut self) -> Result<WinLogEvent> {
+            self.inner.next_event()
+        }
+
+        fn bookmark(&self) -> Result<Bookmark> {
+            self.inner.bookmark()
+        }
+
+        fn close(&mut self) -> Result<()> {
+            self.inner.close()
+        }
+
+        fn wait(&mut self, timeout: Duration) {
+            self.waits.push(timeout);
+            for event in self.later.pop_front().unwrap_or_default() {
+                self.inner.push(event);
+            }
+        }
+    }
+
+    fn growing(batches: Vec<Vec<&str>>) -> Growing {
+        let mut batches: VecDeque<Vec<WinLogEvent>> = batches
+            .into_iter()
+            .map(|b| {
+                b.into_iter()
+                    .map(|e| WinLogEvent::Xml(e.to_owned()))
+                    .collect()
+            })
+            .collect();
+        Growing {
+            inner: MemorySource::new(batches.pop_front().unwrap_or_default()),
+            later: batches,
+            waits: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn drain_stops_at_no_more_logs() {
+        let mut events = growing(vec![vec!["e1", "e2"], vec!["e3"]]).events(Mode::Drain);
+        let read: Vec<String> = events
+            .by_ref()
+            .map(|e| summary(vec![e]).remove(0))
+            .collect();
+        assert_eq!(read, vec!["e1", "e2"]);
+        assert!(events.next().is_none());
+        assert!(events.source().waits.is_empty());
+    }
+
+    #[test]
+    fn drain_continues_after_event_error() {
+        let source = failing(3, vec![(2, ErrorKind::Event), (5, ErrorKind::Subscription)]);
+        let read: Vec<String> = source
+            .events(Mode::Drain)
+            .map(|e| summary(vec![e]).remove(0))
+            .collect();
+        assert_eq!(read, vec!["e1", "bad record 2", "e2", "e3", "bad record 5"]);
+    }
+
+    #[test]
+    fn follow_waits_and_resumes() {
+        let interval = Duration::from_millis(250);
+        // empty batch is a wait without new events
+        let source = growing(vec![vec!["e1"], vec![], vec!["e2", "e3"], vec!["e4"]]);
+        let mut events = source.events(Mode::Follow(interval));
+
+        let read: Vec<String> = events
+            .by_ref()
+            .take(4)
+            .map(|e| summary(vec![e]).remove(0))
+            .collect();
+        assert_eq!(read, vec!["e1", "e2", "e3", "e4"]);
+        assert_eq!(events.source().waits, vec![interval; 3]);
+
+        // non event errors still end the iteration
+        let mut events =
+            failing(1, vec![(2, ErrorKind::Subscription)]).events(Mode::Follow(interval));
+        assert!(events.next().unwrap().is_ok());
+        assert_eq!(
+            events.next().unwrap().unwrap_err().kind,
+            ErrorKind::Subscription
+        );
+        assert!(events.next().is_none());
+    }
+}
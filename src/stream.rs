@@ -0,0 +1,296 @@
+use crate::error::{ErrorKind, Result};
+use crate::event::WinLogEvent;
+use crate::source::{EventSource, Mode};
+
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+// number of events read ahead of the consumer
+const BUFFER_SIZE: usize = 64;
+
+// longest wait on source before checking if the stream was dropped
+const CANCEL_CHECK: Duration = Duration::from_millis(100);
+
+// EventStream pulls events from a source on tokio blocking thread pool so
+// blocking reads never stall async tasks. Dropping the stream stops the
+// worker and closes the source.
+pub struct EventStream {
+    receiver: mpsc::Receiver<Result<WinLogEvent>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl EventStream {
+    // must be called from within tokio runtime
+    pub fn new<S>(source: S, mode: Mode) -> Self
+    where
+        S: EventSource + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(BUFFER_SIZE);
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let flag = cancelled.clone();
+        tokio::task::spawn_blocking(move || run(source, mode, sender, flag));
+
+        Self {
+            receiver,
+            cancelled,
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<WinLogEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.receiver.close();
+    }
+}
+
+fn run<S: EventSource>(
+    mut source: S,
+    mode: Mode,
+    sender: mpsc::Sender<Result<WinLogEvent>>,
+    cancelled: Arc<AtomicBool>,
+) {
+    while !cancelled.load(Ordering::SeqCst) {
+        let event = match source.next_event() {
+            Err(err) if err.kind == ErrorKind::NoMoreLogs => match mode {
+                Mode::Drain => break,
+                Mode::Follow(interval) => {
+                    wait(&mut source, interval, &cancelled);
+                    continue;
+                }
+            },
+            event => event,
+        };
+
+        // event errors are passed on, any other error ends the stream
+        let fatal = matches!(&event, Err(err) if err.kind != ErrorKind::Event);
+
+        if sender.blocking_send(event).is_err() || fatal {
+            break;
+        }
+    }
+
+    let _ = source.close();
+}
+
+// waits up to interval in slices of CANCEL_CHECK, stops early when stream is
+// dropped or source returns before its slice is over, which means new events
+// may be available
+fn wait<S: EventSource>(source: &mut S, interval: Duration, cancelled: &AtomicBool) {
+    let start = Instant::now();
+
+    while !cancelled.load(Ordering::SeqCst) {
+        let slice = interval.saturating_sub(start.elapsed()).min(CANCEL_CHECK);
+        if slice == Duration::ZERO {
+            break;
+        }
+
+        let waited = Instant::now();
+        source.wait(slice);
+        if waited.elapsed() < slice {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookmark::Bookmark;
+    use crate::error::Error;
+    use crate::source::MemorySource;
+    use std::future::Future;
+    use std::sync::Mutex;
+
+    // events of the stream until it ends
+    fn collect(stream: &mut EventStream) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Some(event) = block_on(next(stream)) {
+            events.push(match event {
+                Ok(WinLogEvent::Xml(xml)) => xml,
+                Ok(e) => format!("{:?}", e),
+                Err(err) => err.message,
+            });
+        }
+        events
+    }
+
+    fn next(stream: &mut EventStream) -> impl Future<Output = Option<Result<WinLogEvent>>> + '_ {
+        std::future::poll_fn(move |cx| Pin::new(&mut *stream).poll_next(cx))
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    thread_local! {
+        static RUNTIME: tokio::runtime::Runtime = runtime();
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        RUNTIME.with(|rt| rt.block_on(future))
+    }
+
+    fn stream<S: EventSource + Send + 'static>(source: S, mode: Mode) -> EventStream {
+        RUNTIME.with(|rt| {
+            let _guard = rt.enter();
+            EventStream::new(source, mode)
+        })
+    }
+
+    fn memory(events: &[&str]) -> MemorySource {
+        MemorySource::new(
+            events
+                .iter()
+                .map(|e| WinLogEvent::Xml(e.to_string()))
+                .collect(),
+        )
+    }
+
+    // source returning given results, then NoMoreLogs. Waits sleep unless
+    // wake_up is set, they are recorded and closing is shared with the test.
+    #[derive(Default)]
+    struct Scripted {
+        events: Vec<Result<WinLogEvent>>,
+        on_wait: Vec<WinLogEvent>,
+        wake_up: bool,
+        waits: Arc<Mutex<Vec<Duration>>>,
+        closed: Arc<AtomicBool>,
+    }
+
+    impl EventSource for Scripted {
+        fn next_event(&mut self) -> Result<WinLogEvent> {
+            if self.events.is_empty() {
+                return Err(Error {
+                    kind: ErrorKind::NoMoreLogs,
+                    message: "".to_owned(),
+                });
+            }
+            self.events.remove(0)
+        }
+
+        fn bookmark(&self) -> Result<Bookmark> {
+            Ok(Bookmark::default())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            self.closed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn wait(&mut self, timeout: Duration) {
+            self.waits.lock().unwrap().push(timeout);
+            if self.wake_up {
+                self.events.extend(self.on_wait.drain(..).map(Ok));
+            } else {
+                std::thread::sleep(timeout);
+            }
+        }
+    }
+
+    fn error(kind: ErrorKind, message: &str) -> Result<WinLogEvent> {
+        Err(Error {
+            kind,
+            message: message.to_owned(),
+        })
+    }
+
+    #[test]
+    fn receives_events_until_drained() {
+        let mut events = stream(memory(&["e1", "e2", "e3"]), Mode::Drain);
+        assert_eq!(collect(&mut events), vec!["e1", "e2", "e3"]);
+        assert!(block_on(next(&mut events)).is_none());
+    }
+
+    #[test]
+    fn ends_after_source_error() {
+        let closed = Arc::new(AtomicBool::new(false));
+        let source = Scripted {
+            events: vec![
+                Ok(WinLogEvent::Xml("e1".to_owned())),
+                error(ErrorKind::Event, "bad record"),
+                Ok(WinLogEvent::Xml("e2".to_owned())),
+                error(ErrorKind::Subscription, "subscription closed"),
+                Ok(WinLogEvent::Xml("e3".to_owned())),
+            ],
+            closed: closed.clone(),
+            ..Default::default()
+        };
+        let mut events = stream(source, Mode::Follow(Duration::from_secs(60)));
+        assert_eq!(
+            collect(&mut events),
+            vec!["e1", "bad record", "e2", "subscription closed"]
+        );
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn follow_resumes_when_source_wakes_up() {
+        let waits = Arc::new(Mutex::new(Vec::new()));
+        let source = Scripted {
+            events: vec![Ok(WinLogEvent::Xml("e1".to_owned()))],
+            on_wait: vec![WinLogEvent::Xml("e2".to_owned())],
+            wake_up: true,
+            waits: waits.clone(),
+            ..Default::default()
+        };
+        let mut events = stream(source, Mode::Follow(Duration::from_secs(60)));
+
+        let start = Instant::now();
+        for expected in &["e1", "e2"] {
+            match block_on(next(&mut events)) {
+                Some(Ok(WinLogEvent::Xml(xml))) => assert_eq!(&xml, expected),
+                e => panic!("unexpected {:?}", e),
+            }
+        }
+        // source returned early, so the rest of the interval isn't waited
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(waits.lock().unwrap()[0], CANCEL_CHECK);
+    }
+
+    #[test]
+    fn drop_cancels_waiting_worker() {
+        let waits = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let source = Scripted {
+            events: vec![Ok(WinLogEvent::Xml("e1".to_owned()))],
+            waits: waits.clone(),
+            closed: closed.clone(),
+            ..Default::default()
+        };
+        let mut events = stream(source, Mode::Follow(Duration::from_secs(60)));
+        assert!(block_on(next(&mut events)).unwrap().is_ok());
+
+        // worker is waiting on the empty source when stream is dropped
+        while waits.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let dropped = Instant::now();
+        drop(events);
+
+        while !closed.load(Ordering::SeqCst) {
+            assert!(
+                dropped.elapsed() < Duration::from_secs(5),
+                "source not closed"
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(waits.lock().unwrap().iter().all(|w| *w <= CANCEL_CHECK));
+    }
+}
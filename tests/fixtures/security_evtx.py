@@ -0,0 +1,301 @@
+#!/usr/bin/env python3
+# Generates security.evtx, one chunk with Security 4624 records 1 to 3 of
+# users alice, bob's and carol. System values and event data are template
+# substitutions, event data is a binxml substitution with its own template.
+#
+#   python3 tests/fixtures/security_evtx.py tests/fixtures/security.evtx
+#
+# Layout follows the evtx format description of libevtx, checksums are set so
+# windows tools open the file too.
+
+import struct
+import sys
+import uuid
+import zlib
+
+FILE_HEADER_SIZE = 4096
+CHUNK_SIZE = 65536
+CHUNK_HEADER_SIZE = 512
+
+# value types
+STRING, GUID, UINT8, UINT16, UINT32, UINT64 = 0x01, 0x0F, 0x04, 0x06, 0x08, 0x0A
+FILETIME, SID, HEX_INT64, BINXML, ARRAY = 0x11, 0x13, 0x15, 0x21, 0x80
+NULL = 0x00
+
+PROVIDER_GUID = uuid.UUID("54849625-5478-4994-a5ba-3e3b0328c30d")
+# 2019-04-17T18:40:00Z
+FILETIME_BASE = 132_000_000_000_000_000
+
+
+class Chunk:
+    """Name and template offsets written so far, relative to chunk start."""
+
+    def __init__(self):
+        self.names = {}
+        self.templates = {}
+
+
+class Binxml:
+    """Binxml written at given chunk offset, names and template definitions
+    are inlined on first use."""
+
+    def __init__(self, chunk, offset, substitution=False):
+        self.chunk = chunk
+        self.offset = offset
+        # fragments of binxml substitutions have no element dependency id
+        self.in_substitution = substitution
+        self.buf = bytearray()
+
+    def pos(self):
+        return self.offset + len(self.buf)
+
+    def name(self, name):
+        if name in self.chunk.names:
+            self.buf += struct.pack("<I", self.chunk.names[name])
+            return
+        offset = self.pos() + 4
+        self.chunk.names[name] = offset
+        # offset, next name, hash, length, utf-16 chars, null terminator
+        self.buf += struct.pack("<IIHH", offset, 0, 0, len(name))
+        self.buf += name.encode("utf-16le") + b"\0\0"
+
+    def header(self):
+        self.buf += b"\x0f\x01\x01\x00"
+
+    def open(self, name, attributes=False):
+        self.buf += bytes([0x41 if attributes else 0x01])
+        if not self.in_substitution:
+            self.buf += struct.pack("<H", 0xFFFF)
+        # element size is not used by readers
+        self.buf += struct.pack("<I", 0)
+        self.name(name)
+        if attributes:
+            self.buf += struct.pack("<I", 0)
+
+    def attribute(self, name, more):
+        self.buf += bytes([0x46 if more else 0x06])
+        self.name(name)
+
+    def text(self, text):
+        self.buf += bytes([0x05, STRING]) + struct.pack("<H", len(text))
+        self.buf += text.encode("utf-16le")
+
+    def substitution(self, index, value_type, optional=False):
+        self.buf += bytes([0x0E if optional else 0x0D])
+        self.buf += struct.pack("<HB", index, value_type)
+
+    def close_start(self):
+        self.buf += b"\x02"
+
+    def close_empty(self):
+        self.buf += b"\x03"
+
+    def end(self):
+        self.buf += b"\x04"
+
+    def eof(self):
+        self.buf += b"\x00"
+
+    def element(self, name, value_type, index):
+        self.open(name)
+        self.close_start()
+        self.substitution(index, value_type)
+        self.end()
+
+    def template(self, key, body, values):
+        """Template instance, returns buffer position of each value
+        descriptor and chunk offset of each value. Last value may be left
+        empty and written after the instance."""
+        self.buf += bytes([0x0C, 0x01]) + struct.pack("<I", len(self.chunk.templates))
+        if key in self.chunk.templates:
+            self.buf += struct.pack("<I", self.chunk.templates[key])
+        else:
+            offset = self.pos() + 4
+            self.chunk.templates[key] = offset
+            # definition: next definition, guid, data size, binxml
+            definition = Binxml(self.chunk, offset + 24)
+            body(definition)
+            guid = uuid.uuid5(uuid.NAMESPACE_OID, key).bytes_le
+            self.buf += struct.pack("<II", offset, 0) + guid
+            self.buf += struct.pack("<I", len(definition.buf)) + definition.buf
+
+        self.buf += struct.pack("<I", len(values))
+        descriptors = []
+        for value_type, value in values:
+            descriptors.append(len(self.buf))
+            self.buf += struct.pack("<HBB", len(value), value_type, 0)
+        offsets = []
+        for _, value in values:
+            offsets.append(self.pos())
+            self.buf += value
+        return descriptors, offsets
+
+
+def utf16z(text):
+    return (text + "\0").encode("utf-16le")
+
+
+def system_template(b):
+    b.header()
+    b.open("Event", True)
+    b.attribute("xmlns", False)
+    b.text("http://schemas.microsoft.com/win/2004/08/events/event")
+    b.close_start()
+
+    b.open("System")
+    b.close_start()
+    b.open("Provider", True)
+    b.attribute("Name", True)
+    b.substitution(0, STRING, True)
+    b.attribute("Guid", False)
+    b.substitution(1, GUID, True)
+    b.close_empty()
+    b.open("EventID", True)
+    b.attribute("Qualifiers", False)
+    b.substitution(2, UINT16, True)
+    b.close_start()
+    b.substitution(3, UINT16)
+    b.end()
+    b.element("Version", UINT8, 4)
+    b.element("Level", UINT8, 5)
+    b.element("Task", UINT16, 6)
+    b.element("Opcode", UINT8, 7)
+    b.element("Keywords", HEX_INT64, 8)
+    b.open("TimeCreated", True)
+    b.attribute("SystemTime", False)
+    b.substitution(9, FILETIME, True)
+    b.close_empty()
+    b.element("EventRecordID", UINT64, 10)
+    b.open("Correlation", True)
+    b.attribute("ActivityID", False)
+    b.substitution(11, GUID, True)
+    b.close_empty()
+    b.open("Execution", True)
+    b.attribute("ProcessID", True)
+    b.substitution(12, UINT32)
+    b.attribute("ThreadID", False)
+    b.substitution(13, UINT32)
+    b.close_empty()
+    b.open("Channel")
+    b.close_start()
+    b.text("Security")
+    b.end()
+    b.open("Computer")
+    b.close_start()
+    b.text("DC01 & <x>")
+    b.end()
+    b.open("Security", True)
+    b.attribute("UserID", False)
+    b.substitution(14, SID, True)
+    b.close_empty()
+    b.end()
+
+    b.substitution(15, BINXML, True)
+    b.end()
+    b.eof()
+
+
+def data_template(b):
+    b.header()
+    b.open("EventData")
+    b.close_start()
+    for index, (name, value_type) in enumerate(
+        [("TargetUserName", STRING), ("LogonType", UINT32), ("Flags", ARRAY | STRING)]
+    ):
+        b.open("Data", True)
+        b.attribute("Name", False)
+        b.text(name)
+        b.close_start()
+        b.substitution(index, value_type)
+        b.end()
+    b.end()
+    b.eof()
+
+
+def record(chunk, offset, record_id, user):
+    """Record with header, binxml and trailing size copy."""
+    sid = bytes([1, 1, 0, 0, 0, 0, 0, 5]) + struct.pack("<I", 18)
+    created = FILETIME_BASE + record_id * 10_000_000 + 1_234_567
+
+    b = Binxml(chunk, offset + 24)
+    b.header()
+    values = [
+        (STRING, utf16z("Microsoft-Windows-Security-Auditing")),
+        (GUID, PROVIDER_GUID.bytes_le),
+        (NULL, b""),
+        (UINT16, struct.pack("<H", 4624)),
+        (UINT8, b"\x02"),
+        (UINT8, b"\x00"),
+        (UINT16, struct.pack("<H", 12544)),
+        (UINT8, b"\x00"),
+        (HEX_INT64, struct.pack("<Q", 0x8020000000000000)),
+        (FILETIME, struct.pack("<Q", created)),
+        (UINT64, struct.pack("<Q", record_id)),
+        (NULL, b""),
+        (UINT32, struct.pack("<I", 4)),
+        (UINT32, struct.pack("<I", 88)),
+        (SID, sid),
+        # event data, written below once its size is known
+        (BINXML, b""),
+    ]
+    descriptors, offsets = b.template("system", system_template, values)
+
+    event_data = Binxml(chunk, offsets[-1], substitution=True)
+    event_data.header()
+    event_data.template(
+        "data",
+        data_template,
+        [
+            (STRING, utf16z(user)),
+            (UINT32, struct.pack("<I", 10)),
+            (ARRAY | STRING, utf16z("a") + utf16z("b")),
+        ],
+    )
+    event_data.eof()
+
+    struct.pack_into("<H", b.buf, descriptors[-1], len(event_data.buf))
+    b.buf += event_data.buf
+    b.eof()
+
+    size = 24 + len(b.buf) + 4
+    header = b"**\0\0" + struct.pack("<IQQ", size, record_id, created)
+    return header + bytes(b.buf) + struct.pack("<I", size)
+
+
+def main(path):
+    chunk = Chunk()
+    records = bytearray()
+    offset = CHUNK_HEADER_SIZE
+    for record_id, user in [(1, "alice"), (2, "bob's"), (3, "carol")]:
+        r = record(chunk, offset, record_id, user)
+        records += r
+        offset += len(r)
+
+    data = bytearray(CHUNK_HEADER_SIZE) + records
+    free_space = len(data)
+    data += bytes(CHUNK_SIZE - len(data))
+
+    # signature, first/last record number, first/last record id, header
+    # size, last record offset, free space offset, records checksum
+    data[0:8] = b"ElfChnk\0"
+    last_record = free_space - len(r)
+    struct.pack_into("<QQQQIII", data, 8, 1, 3, 1, 3, 128, last_record, free_space)
+    struct.pack_into("<I", data, 52, zlib.crc32(bytes(data[CHUNK_HEADER_SIZE:free_space])))
+    struct.pack_into("<I", data, 120, 0)
+    struct.pack_into(
+        "<I", data, 124, zlib.crc32(bytes(data[0:120]) + bytes(data[128:CHUNK_HEADER_SIZE]))
+    )
+
+    # signature, first/last chunk, next record id, header size, minor and
+    # major version, header block size, chunk count, flags, checksum
+    header = bytearray(FILE_HEADER_SIZE)
+    header[0:8] = b"ElfFile\0"
+    struct.pack_into("<QQQIHHHH", header, 8, 0, 0, 4, 128, 1, 3, FILE_HEADER_SIZE, 1)
+    struct.pack_into("<I", header, 124, zlib.crc32(bytes(header[0:120])))
+
+    with open(path, "wb") as f:
+        f.write(bytes(header) + bytes(data))
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else "security.evtx")